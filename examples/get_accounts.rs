@@ -1,25 +1,11 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use questrade::{auth::ApiToken, Currency};
+use questrade::{auth::ApiToken, store::EnvFileStore, Currency};
 use tracing::info;
 
 static QT_CONSUMER_KEY: &str = "QT_CONSUMER_KEY";
 static QT_REFRESH_TOKEN: &str = "QT_REFRESH_TOKEN";
 
-fn save_new_token(consumer_key: &str, api_token: &ApiToken) {
-    std::fs::write(
-        ".env",
-        format!(
-            r#"
-export {}={}
-export {}={}
-    "#,
-            QT_CONSUMER_KEY, consumer_key, QT_REFRESH_TOKEN, api_token.refresh_token
-        ),
-    )
-    .unwrap();
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
@@ -27,9 +13,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt().with_env_filter(filter).init();
 
     let consumer_key = std::env::var(QT_CONSUMER_KEY)
-        .expect(&format!("{} env variable must be set", QT_CONSUMER_KEY));
+        .unwrap_or_else(|_| panic!("{} env variable must be set", QT_CONSUMER_KEY));
     let refresh_token = std::env::var(QT_REFRESH_TOKEN)
-        .expect(&format!("{} env variable must be set", QT_REFRESH_TOKEN));
+        .unwrap_or_else(|_| panic!("{} env variable must be set", QT_REFRESH_TOKEN));
 
     let http_client = reqwest::Client::builder()
         .timeout(Duration::from_secs(30))
@@ -40,21 +26,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let c = questrade::client::Client::builder()
         .http_client(http_client)
-        .consumer_key(consumer_key.clone())
+        .consumer_key(consumer_key)
+        .token_store(Arc::new(EnvFileStore::new(".env")))
         .build()?;
 
-    let token = c.refresh_token(&refresh_token).await.unwrap();
-    info!("got token: {:#?}", token);
-
-    // Optional Save the new refresh token to .env file since the old token is now invalid
-    save_new_token(&consumer_key, &token);
+    let session = c.session(ApiToken::from_refresh_token(&refresh_token));
 
-    let accounts = c.accounts(&token).await.unwrap();
+    let accounts = session.accounts().await.unwrap();
     info!("got accounts: {:#?}", accounts);
 
     let mut total_cad = 0.0;
     for account in accounts {
-        let balances = c.account_balances(&token, &account.number).await?;
+        let balances = session.account_balances(&account.number).await?;
         info!("got balances: {:#?}", balances);
         total_cad += balances
             .combined_balances
@@ -0,0 +1,47 @@
+use std::{sync::Arc, time::Duration};
+
+use questrade::{auth::Scope, store::EnvFileStore, Environment};
+use tracing::info;
+
+static QT_CONSUMER_KEY: &str = "QT_CONSUMER_KEY";
+static QT_REDIRECT_URI: &str = "QT_REDIRECT_URI";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "debug".to_owned());
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let consumer_key = std::env::var(QT_CONSUMER_KEY)
+        .unwrap_or_else(|_| panic!("{} env variable must be set", QT_CONSUMER_KEY));
+    let redirect_uri = std::env::var(QT_REDIRECT_URI)
+        .unwrap_or_else(|_| String::from("http://localhost:8080/callback"));
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    let c = questrade::client::Client::builder()
+        .http_client(http_client)
+        .consumer_key(consumer_key)
+        .token_store(Arc::new(EnvFileStore::new(".env")))
+        .env(Environment::Production)
+        .build()?;
+
+    let token = c
+        .login(
+            &redirect_uri,
+            &[Scope::ReadAccounts, Scope::ReadMarketData],
+            |url| {
+                println!(
+                    "Open this URL in your browser to authorize access:\n\n{}\n",
+                    url
+                )
+            },
+        )
+        .await?;
+    info!("got token: {:#?}", token);
+    Ok(())
+}
@@ -3,8 +3,8 @@ use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    auth::ApiToken, client::Client, errors::QuestradeError, AccountStatus, AccountType,
-    ActivityType, ClientAccountType, Currency, StateFilter,
+    auth::ApiToken, client::Client, errors::QuestradeError, response::Response, AccountStatus,
+    AccountType, ActivityType, ClientAccountType, Currency, StateFilter,
 };
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -161,15 +161,24 @@ pub struct Leg {
 
 impl Client {
     pub async fn accounts(&self, token: &ApiToken) -> Result<Vec<Account>, QuestradeError> {
+        self.accounts_with_meta(token)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn accounts_with_meta(
+        &self,
+        token: &ApiToken,
+    ) -> Result<Response<Vec<Account>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Accounts {
             pub accounts: Vec<Account>,
         }
 
-        let data: Accounts = self
-            .send(self.base_request(Method::GET, token, "v1/accounts"))
+        let response: Response<Accounts> = self
+            .send_with_meta(self.base_request(Method::GET, token, "v1/accounts")?)
             .await?;
-        Ok(data.accounts)
+        Ok(response.map(|data| data.accounts))
     }
 
     pub async fn account_activities(
@@ -179,25 +188,37 @@ impl Client {
         start: &DateTime<Utc>,
         end: &DateTime<Utc>,
     ) -> Result<Vec<Activity>, QuestradeError> {
+        self.account_activities_with_meta(token, account_id, start, end)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_activities_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Response<Vec<Activity>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Activities {
             pub activities: Vec<Activity>,
         }
 
-        let data: Activities = self
-            .send(
+        let response: Response<Activities> = self
+            .send_with_meta(
                 self.base_request(
                     Method::GET,
                     token,
                     &format!("v1/accounts/{}/activities", account_id),
-                )
+                )?
                 .query(&[
                     ("startTime", start.to_rfc3339()),
                     ("endTime", end.to_rfc3339()),
                 ]),
             )
             .await?;
-        Ok(data.activities)
+        Ok(response.map(|data| data.activities))
     }
 
     pub async fn account_balances(
@@ -205,11 +226,21 @@ impl Client {
         token: &ApiToken,
         account_id: &str,
     ) -> Result<Balances, QuestradeError> {
-        self.send(self.base_request(
+        self.account_balances_with_meta(token, account_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_balances_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Response<Balances>, QuestradeError> {
+        self.send_with_meta(self.base_request(
             Method::GET,
             token,
             &format!("v1/accounts/{}/balances", account_id),
-        ))
+        )?)
         .await
     }
 
@@ -218,19 +249,29 @@ impl Client {
         token: &ApiToken,
         account_id: &str,
     ) -> Result<Vec<Position>, QuestradeError> {
+        self.account_positions_with_meta(token, account_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_positions_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Response<Vec<Position>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Positions {
             pub positions: Vec<Position>,
         }
 
-        let data: Positions = self
-            .send(self.base_request(
+        let response: Response<Positions> = self
+            .send_with_meta(self.base_request(
                 Method::GET,
                 token,
                 &format!("v1/accounts/{}/positions", account_id),
-            ))
+            )?)
             .await?;
-        Ok(data.positions)
+        Ok(response.map(|data| data.positions))
     }
 
     pub async fn account_executions(
@@ -240,6 +281,18 @@ impl Client {
         start: Option<&DateTime<Utc>>,
         end: Option<&DateTime<Utc>>,
     ) -> Result<Vec<Execution>, QuestradeError> {
+        self.account_executions_with_meta(token, account_id, start, end)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_executions_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+    ) -> Result<Response<Vec<Execution>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Executions {
             pub executions: Vec<Execution>,
@@ -249,7 +302,7 @@ impl Client {
             Method::GET,
             token,
             &format!("v1/accounts/{}/executions", account_id),
-        );
+        )?;
 
         let mut query_params: Vec<(&str, String)> = Vec::new();
         if let Some(start) = start {
@@ -259,8 +312,10 @@ impl Client {
             query_params.push(("endTime", end.to_rfc3339()));
         }
 
-        let data: Executions = self.send(builder.query(query_params.as_slice())).await?;
-        Ok(data.executions)
+        let response: Response<Executions> = self
+            .send_with_meta(builder.query(query_params.as_slice()))
+            .await?;
+        Ok(response.map(|data| data.executions))
     }
 
     pub async fn account_orders(
@@ -271,6 +326,19 @@ impl Client {
         end: Option<&DateTime<Utc>>,
         state_filter: Option<StateFilter>,
     ) -> Result<Vec<Order>, QuestradeError> {
+        self.account_orders_with_meta(token, account_id, start, end, state_filter)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_orders_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    ) -> Result<Response<Vec<Order>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Orders {
             pub orders: Vec<Order>,
@@ -279,7 +347,7 @@ impl Client {
             Method::GET,
             token,
             &format!("v1/accounts/{}/orders", account_id),
-        );
+        )?;
 
         let mut query_params: Vec<(&str, String)> = Vec::new();
         if let Some(start) = start {
@@ -291,8 +359,10 @@ impl Client {
         if let Some(state) = state_filter {
             query_params.push(("stateFilter", format!("{}", state)));
         }
-        let data: Orders = self.send(builder).await?;
-        Ok(data.orders)
+        let response: Response<Orders> = self
+            .send_with_meta(builder.query(query_params.as_slice()))
+            .await?;
+        Ok(response.map(|data| data.orders))
     }
 
     pub async fn account_order(
@@ -301,11 +371,22 @@ impl Client {
         account_id: &str,
         order_id: i64,
     ) -> Result<Order, QuestradeError> {
-        self.send(self.base_request(
+        self.account_order_with_meta(token, account_id, order_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn account_order_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        order_id: i64,
+    ) -> Result<Response<Order>, QuestradeError> {
+        self.send_with_meta(self.base_request(
             Method::GET,
             token,
             &format!("v1/accounts/{}/orders/{}", account_id, order_id),
-        ))
+        )?)
         .await
     }
 }
@@ -343,7 +424,7 @@ mod tests {
             client_account_type: ClientAccountType::Individual,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let account = d.accounts.get(0).unwrap();
+        let account = d.accounts.first().unwrap();
         assert_eq!(expected, account)
     }
 
@@ -398,7 +479,7 @@ mod tests {
             type_: ActivityType::Interest,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let activity = d.activities.get(0).unwrap();
+        let activity = d.activities.first().unwrap();
         assert_eq!(expected, activity);
     }
 
@@ -512,7 +593,7 @@ mod tests {
             is_under_reorg: false,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let position = d.positions.get(0).unwrap();
+        let position = d.positions.first().unwrap();
         assert_eq!(expected, position);
     }
 
@@ -573,7 +654,7 @@ mod tests {
             parent_id: 0,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let position = d.executions.get(0).unwrap();
+        let position = d.executions.first().unwrap();
         assert_eq!(expected, position);
     }
 
@@ -685,7 +766,7 @@ mod tests {
             client_reason_str: None,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let order = d.orders.get(0).unwrap();
+        let order = d.orders.first().unwrap();
         assert_eq!(expected, order);
     }
 }
@@ -0,0 +1,104 @@
+use std::{fmt, sync::Arc};
+
+use url::Url;
+
+use crate::errors::QuestradeError;
+
+pub(crate) type Rewrite = Arc<dyn Fn(&Url) -> Url + Send + Sync>;
+
+/// How the `api_server` Questrade assigns with each token is turned into the base URL of API
+/// requests, and which URLs a client is willing to talk to.
+#[derive(Clone, Default)]
+pub(crate) struct ApiServerPolicy {
+    pub(crate) rewrite: Option<Rewrite>,
+    pub(crate) allow_insecure: bool,
+}
+
+impl fmt::Debug for ApiServerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiServerPolicy")
+            .field("rewrite", &self.rewrite.is_some())
+            .field("allow_insecure", &self.allow_insecure)
+            .finish()
+    }
+}
+
+impl ApiServerPolicy {
+    /// The base URL of API requests made with a token issued for `api_server`, rewritten and
+    /// checked. It always ends in `/`, so API paths extend rather than replace its last segment.
+    pub(crate) fn base_url(&self, api_server: &str) -> Result<Url, QuestradeError> {
+        let mut url = Url::parse(api_server)?;
+        if let Some(rewrite) = &self.rewrite {
+            url = rewrite(&url);
+        }
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        self.check(&url)?;
+        Ok(url)
+    }
+
+    /// The URL of the API endpoint at `path`, e.g. `v1/time`.
+    pub(crate) fn url(&self, api_server: &str, path: &str) -> Result<Url, QuestradeError> {
+        Ok(self.base_url(api_server)?.join(path)?)
+    }
+
+    pub(crate) fn check(&self, url: &Url) -> Result<(), QuestradeError> {
+        if url.scheme() == "https" || self.allow_insecure {
+            Ok(())
+        } else {
+            Err(QuestradeError::InsecureUrl(url.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_url_rewrites_and_checks_api_server() {
+        let policy = ApiServerPolicy::default();
+        assert_eq!(
+            "https://api01.iq.questrade.com/",
+            policy
+                .base_url("https://api01.iq.questrade.com/")
+                .unwrap()
+                .as_str()
+        );
+        assert!(matches!(
+            policy.base_url("http://api01.iq.questrade.com/"),
+            Err(QuestradeError::InsecureUrl(_))
+        ));
+
+        let policy = ApiServerPolicy {
+            rewrite: Some(Arc::new(|url: &Url| {
+                Url::parse("https://proxy.example.com/questrade/")
+                    .unwrap()
+                    .join(url.host_str().unwrap_or_default())
+                    .unwrap()
+            })),
+            allow_insecure: false,
+        };
+        assert_eq!(
+            "https://proxy.example.com/questrade/api01.iq.questrade.com/v1/time",
+            policy
+                .url("https://api01.iq.questrade.com/", "v1/time")
+                .unwrap()
+                .as_str()
+        );
+
+        let policy = ApiServerPolicy {
+            rewrite: None,
+            allow_insecure: true,
+        };
+        assert_eq!(
+            "http://127.0.0.1:8080/prefix/v1/time",
+            policy
+                .url("http://127.0.0.1:8080/prefix", "v1/time")
+                .unwrap()
+                .as_str()
+        );
+    }
+}
@@ -1,33 +1,869 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use url::Url;
+use uuid::Uuid;
 
-use crate::{client::Client, errors::QuestradeError};
+use crate::{client::Client, errors::QuestradeError, token::DEFAULT_REFRESH_MARGIN, Environment};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct ApiServer {
-    api_server: String,
-}
+const MAX_CALLBACK_REQUEST_SIZE: usize = 8 * 1024;
+/// How long a connection to the loopback listener may take to send its request, so an idle
+/// connection, such as a browser preconnect, can't hold up the callback.
+pub(crate) const CALLBACK_READ_TIMEOUT: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct ApiToken {
     pub access_token: String,
     pub token_type: String,
     pub refresh_token: String,
     pub api_server: String,
     pub expires_in: usize,
+    /// Tokens saved before this field existed load as issued long ago, and so as expired.
+    #[serde(default = "issued_long_ago")]
+    pub issued_at: DateTime<Utc>,
+}
+
+fn issued_long_ago() -> DateTime<Utc> {
+    DateTime::<Utc>::from(std::time::UNIX_EPOCH)
+}
+
+/// A token as returned by the token endpoint, which leaves out when it was issued.
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    refresh_token: String,
+    api_server: String,
+    expires_in: usize,
+}
+
+impl From<TokenResponse> for ApiToken {
+    fn from(response: TokenResponse) -> Self {
+        ApiToken {
+            access_token: response.access_token,
+            token_type: response.token_type,
+            refresh_token: response.refresh_token,
+            api_server: response.api_server,
+            expires_in: response.expires_in,
+            issued_at: Utc::now(),
+        }
+    }
+}
+
+impl ApiToken {
+    /// An already expired token holding only a refresh token, such as one generated by hand in
+    /// the Questrade App Hub. It is refreshed on first use.
+    pub fn from_refresh_token(refresh_token: &str) -> Self {
+        ApiToken {
+            access_token: String::new(),
+            token_type: String::from("Bearer"),
+            refresh_token: String::from(refresh_token),
+            api_server: String::new(),
+            expires_in: 0,
+            issued_at: issued_long_ago(),
+        }
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.issued_at + chrono::Duration::seconds(self.expires_in as i64)
+    }
+
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        let margin =
+            chrono::Duration::from_std(margin).unwrap_or_else(|_| chrono::Duration::zero());
+        Utc::now() + margin >= self.expires_at()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, strum_macros::Display, strum_macros::EnumIter)]
+pub enum Scope {
+    #[strum(serialize = "read_acc")]
+    ReadAccounts,
+    #[strum(serialize = "read_md")]
+    ReadMarketData,
+    #[strum(serialize = "trade")]
+    Trade,
 }
 
 impl Client {
+    /// Exchanges a refresh token for a new [`ApiToken`], invalidating `refresh_token`.
+    ///
+    /// With a token store configured the refresh is coordinated with other processes using the
+    /// same store: the store is locked for the duration of the refresh, and if the stored token
+    /// shows another process already rotated `refresh_token`, that token is reused (or refreshed
+    /// in turn if it has expired) instead.
     pub async fn refresh_token(&self, refresh_token: &str) -> Result<ApiToken, QuestradeError> {
+        self.refresh_token_within(refresh_token, DEFAULT_REFRESH_MARGIN)
+            .await
+    }
+
+    /// Like [`Client::refresh_token`], but a rotated token in the store is only reused if it stays
+    /// valid for at least `refresh_margin`.
+    pub(crate) async fn refresh_token_within(
+        &self,
+        refresh_token: &str,
+        refresh_margin: Duration,
+    ) -> Result<ApiToken, QuestradeError> {
+        let _lock = self.lock_token_store().await?;
+        let refresh_token = match rotation(self.load_token()?, refresh_token, refresh_margin) {
+            Rotation::Reuse(stored) => return Ok(stored),
+            Rotation::Refresh(refresh_token) => refresh_token,
+        };
+
         let params = [
             ("client_id", self.consumer_key.clone()),
-            ("refresh_token", String::from(refresh_token)),
+            ("refresh_token", refresh_token),
             ("grant_type", String::from("refresh_token")),
         ];
-        self.send(
-            self.http
-                .request(reqwest::Method::POST, self.env.token_url()?)
-                .form(&params),
+        let token: TokenResponse = self
+            .send(
+                self.http
+                    .request(reqwest::Method::POST, self.env.token_url()?)
+                    .form(&params),
+            )
+            .await?;
+        self.persist_token(token.into())
+    }
+
+    /// Builds the URL the user has to visit to grant this application access. The `state` is
+    /// echoed back on the redirect and must be checked by the caller to prevent CSRF.
+    pub fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        scopes: &[Scope],
+        state: &str,
+    ) -> Result<Url, QuestradeError> {
+        authorize_url(&self.env, &self.consumer_key, redirect_uri, scopes, state)
+    }
+
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        let params = [
+            ("client_id", self.consumer_key.clone()),
+            ("code", String::from(code)),
+            ("grant_type", String::from("authorization_code")),
+            ("redirect_uri", String::from(redirect_uri)),
+        ];
+        let token: TokenResponse = self
+            .send(
+                self.http
+                    .request(reqwest::Method::POST, self.env.token_url()?)
+                    .form(&params),
+            )
+            .await?;
+        self.persist_token(token.into())
+    }
+
+    /// Runs the full authorization code flow. A loopback listener is bound to `redirect_uri`
+    /// (which must be an `http://localhost` style URL registered with the Questrade App Hub),
+    /// `open` is handed the authorize URL to show to the user, and the code received on the
+    /// redirect is exchanged for an [`ApiToken`].
+    pub async fn login<F>(
+        &self,
+        redirect_uri: &str,
+        scopes: &[Scope],
+        open: F,
+    ) -> Result<ApiToken, QuestradeError>
+    where
+        F: FnOnce(&Url),
+    {
+        let redirect = Url::parse(redirect_uri)?;
+        let listener = bind_loopback(&redirect).await?;
+        let state = Uuid::new_v4().to_string();
+
+        open(&self.authorize_url(redirect_uri, scopes, &state)?);
+
+        let code =
+            wait_for_callback(&listener, redirect.path(), &state, CALLBACK_READ_TIMEOUT).await?;
+        self.exchange_code(&code, redirect_uri).await
+    }
+}
+
+pub(crate) fn authorize_url(
+    env: &Environment,
+    consumer_key: &str,
+    redirect_uri: &str,
+    scopes: &[Scope],
+    state: &str,
+) -> Result<Url, QuestradeError> {
+    let mut url = env.authorize_url()?;
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("client_id", consumer_key)
+            .append_pair("response_type", "code")
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("state", state);
+        if !scopes.is_empty() {
+            let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+            query.append_pair("scope", &scopes.join(" "));
+        }
+    }
+    Ok(url)
+}
+
+/// What a refresh of a given refresh token should do, based on the token in the store.
+pub(crate) enum Rotation {
+    /// Another process already rotated the refresh token and its access token is still fresh.
+    Reuse(ApiToken),
+    /// Refresh using this refresh token, which may be a newer one found in the store.
+    Refresh(String),
+}
+
+pub(crate) fn rotation(
+    stored: Option<ApiToken>,
+    refresh_token: &str,
+    refresh_margin: Duration,
+) -> Rotation {
+    match stored {
+        Some(stored)
+            if !stored.refresh_token.is_empty() && stored.refresh_token != refresh_token =>
+        {
+            if !stored.expires_within(refresh_margin) {
+                tracing::debug!("token already rotated by another process, reusing it");
+                Rotation::Reuse(stored)
+            } else {
+                Rotation::Refresh(stored.refresh_token)
+            }
+        }
+        _ => Rotation::Refresh(String::from(refresh_token)),
+    }
+}
+
+async fn bind_loopback(redirect: &Url) -> Result<TcpListener, QuestradeError> {
+    Ok(TcpListener::bind(loopback_addr(redirect)?).await?)
+}
+
+/// The address to listen on for redirects to `redirect`, which must be a loopback `http` URL.
+pub(crate) fn loopback_addr(redirect: &Url) -> Result<String, QuestradeError> {
+    if redirect.scheme() != "http" {
+        return Err(QuestradeError::AuthorizationError(format!(
+            "redirect_uri must use http for the loopback listener, got {}",
+            redirect.scheme()
+        )));
+    }
+    let host = match redirect.host_str() {
+        Some("localhost") => "127.0.0.1",
+        Some(host @ "127.0.0.1") | Some(host @ "[::1]") => host,
+        _ => {
+            return Err(QuestradeError::AuthorizationError(format!(
+                "redirect_uri must point at a loopback address, got {}",
+                redirect
+            )))
+        }
+    };
+    let port = redirect.port_or_known_default().unwrap_or(80);
+    Ok(format!("{}:{}", host, port))
+}
+
+async fn wait_for_callback(
+    listener: &TcpListener,
+    path: &str,
+    state: &str,
+    read_timeout: Duration,
+) -> Result<String, QuestradeError> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let request_line =
+            match tokio::time::timeout(read_timeout, read_request_line(&mut stream)).await {
+                Ok(Ok(line)) => line,
+                Ok(Err(err)) => {
+                    tracing::debug!("ignoring malformed callback request: {}", err);
+                    continue;
+                }
+                Err(_) => {
+                    tracing::debug!("ignoring callback connection that sent no request in time");
+                    continue;
+                }
+            };
+
+        let answer = answer_callback(&request_line, path, state);
+        respond(&mut stream, &answer).await;
+        if let Some(outcome) = answer.outcome {
+            return outcome;
+        }
+    }
+}
+
+async fn read_request_line(stream: &mut TcpStream) -> Result<String, QuestradeError> {
+    let mut request = CallbackRequest::default();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if let Some(line) = request.push(&chunk[..n])? {
+            return Ok(line);
+        }
+    }
+}
+
+/// Answers a callback request. The browser may already have gone away, which is only logged.
+async fn respond(stream: &mut TcpStream, answer: &CallbackAnswer) {
+    let written = match stream.write_all(answer.to_http().as_bytes()).await {
+        Ok(()) => stream.shutdown().await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = written {
+        tracing::debug!("failed to answer callback request: {}", err);
+    }
+}
+
+/// Collects a request to the loopback server as it is read, until its head is complete.
+#[derive(Debug, Default)]
+pub(crate) struct CallbackRequest {
+    buf: Vec<u8>,
+}
+
+impl CallbackRequest {
+    /// Adds the bytes of one read, an empty read meaning the connection was closed. Returns the
+    /// request line once the head has been read.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Result<Option<String>, QuestradeError> {
+        self.buf.extend_from_slice(chunk);
+        if self.buf.len() > MAX_CALLBACK_REQUEST_SIZE {
+            return Err(QuestradeError::AuthorizationError(String::from(
+                "callback request too large",
+            )));
+        }
+        if !chunk.is_empty() && !self.buf.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(None);
+        }
+        String::from_utf8_lossy(&self.buf)
+            .lines()
+            .next()
+            .map(|line| Some(String::from(line)))
+            .ok_or_else(|| {
+                QuestradeError::AuthorizationError(String::from("empty callback request"))
+            })
+    }
+}
+
+/// What the loopback server answers to a request, and how the login ends if the request was the
+/// callback.
+#[derive(Debug)]
+pub(crate) struct CallbackAnswer {
+    status: &'static str,
+    body: &'static str,
+    /// The authorization code or the reason authorization failed, `None` for unrelated requests.
+    pub(crate) outcome: Option<Result<String, QuestradeError>>,
+}
+
+impl CallbackAnswer {
+    pub(crate) fn to_http(&self) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+pub(crate) fn answer_callback(request_line: &str, path: &str, state: &str) -> CallbackAnswer {
+    let (status, body, outcome) = match parse_callback(request_line, path, state) {
+        Ok(Some(code)) => (
+            "200 OK",
+            "Authorization complete, you may close this window.",
+            Some(Ok(code)),
+        ),
+        Ok(None) => ("404 Not Found", "Not found.", None),
+        Err(err) => ("400 Bad Request", "Authorization failed.", Some(Err(err))),
+    };
+    CallbackAnswer {
+        status,
+        body,
+        outcome,
+    }
+}
+
+/// Parses the request line of a redirect, returning the authorization code if the request was
+/// for the callback path, or `None` for unrelated requests such as `/favicon.ico`.
+fn parse_callback(
+    request_line: &str,
+    path: &str,
+    state: &str,
+) -> Result<Option<String>, QuestradeError> {
+    let target = request_line.split_whitespace().nth(1).ok_or_else(|| {
+        QuestradeError::AuthorizationError(format!("malformed request line: {}", request_line))
+    })?;
+    let url = Url::parse("http://localhost")?.join(target)?;
+    if url.path() != path {
+        return Ok(None);
+    }
+
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+
+    if let Some(error) = param("error") {
+        let description = param("error_description").unwrap_or_default();
+        return Err(QuestradeError::AuthorizationError(format!(
+            "authorization denied: {} {}",
+            error, description
+        )));
+    }
+    if param("state").as_deref() != Some(state) {
+        return Err(QuestradeError::AuthorizationError(String::from(
+            "state mismatch in authorization callback",
+        )));
+    }
+    param("code")
+        .map(Some)
+        .ok_or_else(|| QuestradeError::AuthorizationError(String::from("missing code in callback")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    use super::*;
+    use crate::{
+        store::{JsonFileStore, MemoryStore, TokenStore},
+        test_server::{test_client_builder, TestResponse, TestServer},
+        token::TokenManager,
+        Environment,
+    };
+
+    fn client() -> Client {
+        test_client_builder()
+            .env(Environment::Practice)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn api_token_deserialize_works() {
+        let data = r#"
+        {
+            "access_token": "C3lTUKuNQrAAmSD/TPjuV/HI7aNrAwDp",
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "refresh_token": "aSBe7wAAdx88QTbwut0tiu3SYic3ox8F",
+            "api_server": "https://api01.iq.questrade.com"
+        }
+        "#;
+        let before = Utc::now();
+        let token: TokenResponse = serde_json::from_str(data).expect("failed to deserialize JSON");
+        let token = ApiToken::from(token);
+        assert!(token.issued_at >= before && token.issued_at <= Utc::now());
+        assert_eq!(
+            token.issued_at + chrono::Duration::seconds(300),
+            token.expires_at()
+        );
+        assert!(!token.expires_within(Duration::from_secs(60)));
+        assert!(token.expires_within(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn api_token_without_issued_at_loads_as_expired() {
+        let data = r#"
+        {
+            "access_token": "C3lTUKuNQrAAmSD/TPjuV/HI7aNrAwDp",
+            "token_type": "Bearer",
+            "expires_in": 1800,
+            "refresh_token": "aSBe7wAAdx88QTbwut0tiu3SYic3ox8F",
+            "api_server": "https://api01.iq.questrade.com"
+        }
+        "#;
+        let token: ApiToken = serde_json::from_str(data).expect("failed to deserialize JSON");
+        assert!(token.expires_within(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn scope_display_works() {
+        <Scope as strum::IntoEnumIterator>::iter().for_each(|s| {
+            let expected_string = match s {
+                Scope::ReadAccounts => "read_acc",
+                Scope::ReadMarketData => "read_md",
+                Scope::Trade => "trade",
+            };
+            assert_eq!(expected_string, format!("{}", s));
+        })
+    }
+
+    #[test]
+    fn authorize_url_works() {
+        let url = client()
+            .authorize_url(
+                "http://localhost:8080/callback",
+                &[Scope::ReadAccounts, Scope::ReadMarketData],
+                "abc",
+            )
+            .unwrap();
+        assert_eq!(
+            "https://practicelogin.questrade.com/oauth2/authorize?client_id=consumer&response_type=code&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback&state=abc&scope=read_acc+read_md",
+            url.as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn login_exchanges_code() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(
+                200,
+                r#"{"access_token":"a1","token_type":"Bearer","expires_in":1800,"refresh_token":"r1","api_server":"https://api01.iq.questrade.com/"}"#,
+            )
+        })
+        .await;
+        let store = Arc::new(MemoryStore::new());
+        let client = test_client_builder()
+            .env(Environment::Custom {
+                login_url: Url::parse(&server.url).unwrap(),
+            })
+            .token_store(store.clone())
+            .build()
+            .unwrap();
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+        let token = client
+            .login(&redirect_uri, &[Scope::ReadAccounts], |url| {
+                assert_eq!("/oauth2/authorize", url.path());
+                let state = url
+                    .query_pairs()
+                    .find(|(k, _)| k == "state")
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap();
+                let callback = format!("{}?code=xyz&state={}", redirect_uri, state);
+                tokio::spawn(async move { reqwest::get(callback).await.unwrap() });
+            })
+            .await
+            .unwrap();
+        assert_eq!("a1", token.access_token);
+        assert_eq!(Some(token), store.load().unwrap());
+
+        let request = &server.requests()[0];
+        assert_eq!("/oauth2/token", request.target);
+        assert!(request.body.contains("code=xyz"));
+        assert!(request.body.contains("grant_type=authorization_code"));
+    }
+
+    #[tokio::test]
+    async fn wait_for_callback_skips_idle_and_malformed_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        drop(TcpStream::connect(addr).await.unwrap());
+        let mut callback = TcpStream::connect(addr).await.unwrap();
+        callback
+            .write_all(b"GET /callback?code=xyz&state=abc HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        let code = wait_for_callback(&listener, "/callback", "abc", Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!("xyz", code);
+        let mut response = String::new();
+        callback.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    #[test]
+    fn callback_request_waits_for_the_head() {
+        let mut request = CallbackRequest::default();
+        assert_eq!(None, request.push(b"GET /callback?code=xyz").unwrap());
+        assert_eq!(None, request.push(b" HTTP/1.1\r\nHost: x\r\n").unwrap());
+        assert_eq!(
+            Some(String::from("GET /callback?code=xyz HTTP/1.1")),
+            request.push(b"\r\n").unwrap()
+        );
+
+        // A closed connection ends the head early.
+        let mut request = CallbackRequest::default();
+        request.push(b"GET / HTTP/1.1").unwrap();
+        assert_eq!(
+            Some(String::from("GET / HTTP/1.1")),
+            request.push(b"").unwrap()
+        );
+        assert!(CallbackRequest::default().push(b"").is_err());
+
+        let mut request = CallbackRequest::default();
+        let err = request.push(&[b'a'; MAX_CALLBACK_REQUEST_SIZE + 1]);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn answer_callback_works() {
+        let answer = answer_callback(
+            "GET /callback?code=xyz&state=abc HTTP/1.1",
+            "/callback",
+            "abc",
+        );
+        assert!(answer.to_http().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!("xyz", answer.outcome.unwrap().unwrap());
+
+        let answer = answer_callback("GET /favicon.ico HTTP/1.1", "/callback", "abc");
+        assert!(answer.to_http().starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(answer.outcome.is_none());
+
+        let answer = answer_callback(
+            "GET /callback?code=xyz&state=evil HTTP/1.1",
+            "/callback",
+            "abc",
+        );
+        assert!(answer.to_http().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(answer.outcome.unwrap().is_err());
+    }
+
+    #[test]
+    fn parse_callback_works() {
+        let code = parse_callback(
+            "GET /callback?code=xyz&state=abc HTTP/1.1",
+            "/callback",
+            "abc",
+        )
+        .unwrap();
+        assert_eq!(Some(String::from("xyz")), code);
+    }
+
+    #[test]
+    fn parse_callback_ignores_other_paths() {
+        let code = parse_callback("GET /favicon.ico HTTP/1.1", "/callback", "abc").unwrap();
+        assert_eq!(None, code);
+    }
+
+    #[test]
+    fn parse_callback_rejects_state_mismatch() {
+        let err = parse_callback(
+            "GET /callback?code=xyz&state=evil HTTP/1.1",
+            "/callback",
+            "abc",
+        )
+        .unwrap_err();
+        assert!(matches!(err, QuestradeError::AuthorizationError(_)));
+    }
+
+    #[test]
+    fn parse_callback_surfaces_denial() {
+        let err = parse_callback(
+            "GET /callback?error=access_denied&state=abc HTTP/1.1",
+            "/callback",
+            "abc",
         )
-        .await
+        .unwrap_err();
+        assert!(matches!(err, QuestradeError::AuthorizationError(_)));
+    }
+
+    static CHILD_LOGIN_URL: &str = "QT_REFRESH_LOCK_LOGIN_URL";
+    static CHILD_TOKEN_FILE: &str = "QT_REFRESH_LOCK_TOKEN_FILE";
+
+    /// A token endpoint that, like Questrade's, only accepts the most recently issued refresh
+    /// token.
+    #[derive(Default)]
+    struct TokenEndpoint {
+        generation: Mutex<u32>,
+        refreshes: AtomicUsize,
+    }
+
+    impl TokenEndpoint {
+        async fn start(generation: u32) -> (Arc<Self>, Url) {
+            let endpoint = Arc::new(TokenEndpoint {
+                generation: Mutex::new(generation),
+                refreshes: AtomicUsize::new(0),
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+
+            let server = endpoint.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(server.clone().handle(stream));
+                }
+            });
+            (endpoint, url)
+        }
+
+        async fn handle(self: Arc<Self>, mut stream: TcpStream) {
+            let body = read_body(&mut stream).await;
+            let refresh_token = url::form_urlencoded::parse(body.as_bytes())
+                .find(|(k, _)| k == "refresh_token")
+                .map(|(_, v)| v.into_owned())
+                .unwrap_or_default();
+
+            // Widen the window in which unsynchronized processes would race each other.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let issued = {
+                let mut generation = self.generation.lock().unwrap();
+                if refresh_token == format!("r{}", *generation) {
+                    *generation += 1;
+                    self.refreshes.fetch_add(1, Ordering::SeqCst);
+                    Some(*generation)
+                } else {
+                    None
+                }
+            };
+            let (status, body) = match issued {
+                Some(generation) => (
+                    "200 OK",
+                    format!(
+                        r#"{{"access_token":"a{0}","token_type":"Bearer","expires_in":1800,"refresh_token":"r{0}","api_server":"http://127.0.0.1:1/"}}"#,
+                        generation
+                    ),
+                ),
+                None => (
+                    "400 Bad Request",
+                    String::from(r#"{"code":1017,"message":"Access token is invalid"}"#),
+                ),
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn read_body(stream: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            let request = String::from_utf8_lossy(&buf).into_owned();
+            if let Some((head, body)) = request.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length || n == 0 {
+                    return String::from(body);
+                }
+            }
+        }
+    }
+
+    fn locked_client(login_url: Url, store: Arc<JsonFileStore>) -> Client {
+        test_client_builder()
+            .env(Environment::Custom { login_url })
+            .token_store(store)
+            .build()
+            .unwrap()
+    }
+
+    /// Runs inside the child processes spawned by `processes_sharing_a_token_file_refresh_once`.
+    #[tokio::test]
+    async fn refresh_lock_child() {
+        let (login_url, token_file) = match (
+            std::env::var(CHILD_LOGIN_URL),
+            std::env::var(CHILD_TOKEN_FILE),
+        ) {
+            (Ok(login_url), Ok(token_file)) => (login_url, token_file),
+            _ => return,
+        };
+        let store = Arc::new(JsonFileStore::new(token_file));
+        let client = locked_client(Url::parse(&login_url).unwrap(), store.clone());
+
+        let stale = store.load().unwrap().unwrap();
+        let token = TokenManager::new(stale).token(&client).await.unwrap();
+        println!("ACCESS_TOKEN={}", token.access_token);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn processes_sharing_a_token_file_refresh_once() {
+        if std::env::var(CHILD_LOGIN_URL).is_ok() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        JsonFileStore::new(&path)
+            .save(&ApiToken::from_refresh_token("r0"))
+            .unwrap();
+        let (endpoint, login_url) = TokenEndpoint::start(0).await;
+
+        let children: Vec<_> = (0..6)
+            .map(|_| {
+                tokio::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["auth::tests::refresh_lock_child", "--exact", "--nocapture"])
+                    .env(CHILD_LOGIN_URL, login_url.as_str())
+                    .env(CHILD_TOKEN_FILE, &path)
+                    .output()
+            })
+            .collect();
+
+        for child in children {
+            let output = child.await.unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(
+                output.status.success(),
+                "child failed: {}{}",
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            assert!(
+                stdout
+                    .split_whitespace()
+                    .any(|word| word == "ACCESS_TOKEN=a1"),
+                "{}",
+                stdout
+            );
+        }
+
+        assert_eq!(1, endpoint.refreshes.load(Ordering::SeqCst));
+        let stored = JsonFileStore::new(&path).load().unwrap().unwrap();
+        assert_eq!("r1", stored.refresh_token);
+    }
+
+    #[tokio::test]
+    async fn refresh_uses_token_rotated_by_another_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(JsonFileStore::new(dir.path().join("token.json")));
+        let (endpoint, login_url) = TokenEndpoint::start(1).await;
+        let client = locked_client(login_url, store.clone());
+
+        // Another process rotated r0 into r1 and its access token has since expired.
+        store.save(&ApiToken::from_refresh_token("r1")).unwrap();
+
+        let token = client.refresh_token("r0").await.unwrap();
+        assert_eq!("a2", token.access_token);
+        assert_eq!(1, endpoint.refreshes.load(Ordering::SeqCst));
+        assert_eq!("r2", store.load().unwrap().unwrap().refresh_token);
+
+        // The token just saved is still fresh, so refreshing with r1 again reuses it.
+        let token = client.refresh_token("r1").await.unwrap();
+        assert_eq!("a2", token.access_token);
+        assert_eq!(1, endpoint.refreshes.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn refresh_honours_token_manager_margin() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(JsonFileStore::new(dir.path().join("token.json")));
+        let (endpoint, login_url) = TokenEndpoint::start(0).await;
+        let client = locked_client(login_url, store.clone());
+
+        // Another process rotated r0 into r1, its token is fresh by the default margin only.
+        let rotated = client.refresh_token("r0").await.unwrap();
+        assert_eq!(1, endpoint.refreshes.load(Ordering::SeqCst));
+
+        let stale = ApiToken::from_refresh_token("r0");
+        let manager = TokenManager::new(stale).refresh_margin(Duration::from_secs(3600));
+        let token = manager.token(&client).await.unwrap();
+        assert_ne!(rotated.access_token, token.access_token);
+        assert_eq!(2, endpoint.refreshes.load(Ordering::SeqCst));
     }
 }
@@ -0,0 +1,72 @@
+use std::{sync::Arc, time::Duration};
+
+use questrade::{
+    auth::ApiToken,
+    broker::TokenBroker,
+    client::Client,
+    store::{JsonFileStore, TokenStore},
+    token::TokenManager,
+    Environment,
+};
+use tracing::info;
+
+static QT_BROKER_SOCKET: &str = "QT_BROKER_SOCKET";
+static QT_CONSUMER_KEY: &str = "QT_CONSUMER_KEY";
+static QT_ENVIRONMENT: &str = "QT_ENVIRONMENT";
+static QT_REFRESH_TOKEN: &str = "QT_REFRESH_TOKEN";
+static QT_TOKEN_FILE: &str = "QT_TOKEN_FILE";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned());
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let consumer_key = std::env::var(QT_CONSUMER_KEY)
+        .unwrap_or_else(|_| panic!("{} env variable must be set", QT_CONSUMER_KEY));
+    let socket = std::env::var(QT_BROKER_SOCKET)
+        .unwrap_or_else(|_| String::from("/tmp/questrade-token-broker.sock"));
+    let token_file =
+        std::env::var(QT_TOKEN_FILE).unwrap_or_else(|_| String::from("questrade-token.json"));
+    let env = match std::env::var(QT_ENVIRONMENT).as_deref() {
+        Ok("practice") => Environment::Practice,
+        _ => Environment::Production,
+    };
+
+    let store = Arc::new(JsonFileStore::new(&token_file));
+    let token = match store.load()? {
+        Some(token) => token,
+        None => {
+            ApiToken::from_refresh_token(&std::env::var(QT_REFRESH_TOKEN).unwrap_or_else(|_| {
+                panic!(
+                    "{} is empty, {} env variable must be set",
+                    token_file, QT_REFRESH_TOKEN
+                )
+            }))
+        }
+    };
+
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(10))
+        .build()?;
+    let client = Client::builder()
+        .http_client(http_client)
+        .consumer_key(consumer_key)
+        .env(env)
+        .token_store(store)
+        .build()?;
+
+    let tokens = TokenManager::new(token);
+    tokens.token(&client).await?;
+
+    let broker = Arc::new(TokenBroker::new(client, tokens));
+    let listener = TokenBroker::bind(&socket)?;
+    info!("token broker listening on {}", socket);
+
+    tokio::select! {
+        result = broker.serve(listener) => result?,
+        _ = tokio::signal::ctrl_c() => info!("shutting down"),
+    }
+    std::fs::remove_file(&socket)?;
+    Ok(())
+}
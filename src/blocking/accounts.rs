@@ -0,0 +1,254 @@
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::Deserialize;
+
+use super::Client;
+use crate::{
+    accounts::{Account, Activity, Balances, Execution, Order, Position},
+    auth::ApiToken,
+    errors::QuestradeError,
+    response::Response,
+    StateFilter,
+};
+
+impl Client {
+    pub fn accounts(&self, token: &ApiToken) -> Result<Vec<Account>, QuestradeError> {
+        self.accounts_with_meta(token).map(Response::into_data)
+    }
+
+    pub fn accounts_with_meta(
+        &self,
+        token: &ApiToken,
+    ) -> Result<Response<Vec<Account>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Accounts {
+            pub accounts: Vec<Account>,
+        }
+
+        let response: Response<Accounts> =
+            self.send_with_meta(self.base_request(Method::GET, token, "v1/accounts")?)?;
+        Ok(response.map(|data| data.accounts))
+    }
+
+    pub fn account_activities(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Activity>, QuestradeError> {
+        self.account_activities_with_meta(token, account_id, start, end)
+            .map(Response::into_data)
+    }
+
+    pub fn account_activities_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Response<Vec<Activity>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Activities {
+            pub activities: Vec<Activity>,
+        }
+
+        let response: Response<Activities> = self.send_with_meta(
+            self.base_request(
+                Method::GET,
+                token,
+                &format!("v1/accounts/{}/activities", account_id),
+            )?
+            .query(&[
+                ("startTime", start.to_rfc3339()),
+                ("endTime", end.to_rfc3339()),
+            ]),
+        )?;
+        Ok(response.map(|data| data.activities))
+    }
+
+    pub fn account_balances(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Balances, QuestradeError> {
+        self.account_balances_with_meta(token, account_id)
+            .map(Response::into_data)
+    }
+
+    pub fn account_balances_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Response<Balances>, QuestradeError> {
+        self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/accounts/{}/balances", account_id),
+        )?)
+    }
+
+    pub fn account_positions(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Vec<Position>, QuestradeError> {
+        self.account_positions_with_meta(token, account_id)
+            .map(Response::into_data)
+    }
+
+    pub fn account_positions_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+    ) -> Result<Response<Vec<Position>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Positions {
+            pub positions: Vec<Position>,
+        }
+
+        let response: Response<Positions> = self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/accounts/{}/positions", account_id),
+        )?)?;
+        Ok(response.map(|data| data.positions))
+    }
+
+    pub fn account_executions(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+    ) -> Result<Vec<Execution>, QuestradeError> {
+        self.account_executions_with_meta(token, account_id, start, end)
+            .map(Response::into_data)
+    }
+
+    pub fn account_executions_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+    ) -> Result<Response<Vec<Execution>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Executions {
+            pub executions: Vec<Execution>,
+        }
+
+        let builder = self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/accounts/{}/executions", account_id),
+        )?;
+
+        let mut query_params: Vec<(&str, String)> = Vec::new();
+        if let Some(start) = start {
+            query_params.push(("startTime", start.to_rfc3339()));
+        };
+        if let Some(end) = end {
+            query_params.push(("endTime", end.to_rfc3339()));
+        }
+
+        let response: Response<Executions> =
+            self.send_with_meta(builder.query(query_params.as_slice()))?;
+        Ok(response.map(|data| data.executions))
+    }
+
+    pub fn account_orders(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    ) -> Result<Vec<Order>, QuestradeError> {
+        self.account_orders_with_meta(token, account_id, start, end, state_filter)
+            .map(Response::into_data)
+    }
+
+    pub fn account_orders_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    ) -> Result<Response<Vec<Order>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Orders {
+            pub orders: Vec<Order>,
+        }
+        let builder = self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/accounts/{}/orders", account_id),
+        )?;
+
+        let mut query_params: Vec<(&str, String)> = Vec::new();
+        if let Some(start) = start {
+            query_params.push(("startTime", start.to_rfc3339()));
+        };
+        if let Some(end) = end {
+            query_params.push(("endTime", end.to_rfc3339()));
+        }
+        if let Some(state) = state_filter {
+            query_params.push(("stateFilter", format!("{}", state)));
+        }
+        let response: Response<Orders> =
+            self.send_with_meta(builder.query(query_params.as_slice()))?;
+        Ok(response.map(|data| data.orders))
+    }
+
+    pub fn account_order(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        order_id: i64,
+    ) -> Result<Order, QuestradeError> {
+        self.account_order_with_meta(token, account_id, order_id)
+            .map(Response::into_data)
+    }
+
+    pub fn account_order_with_meta(
+        &self,
+        token: &ApiToken,
+        account_id: &str,
+        order_id: i64,
+    ) -> Result<Response<Order>, QuestradeError> {
+        self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/accounts/{}/orders/{}", account_id, order_id),
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blocking::client::tests::{client, server},
+        test_server::{FakeTokens, TestResponse},
+    };
+
+    #[test]
+    fn account_executions_works() {
+        let (_runtime, server) = server(|_| TestResponse::json(200, r#"{"executions": []}"#));
+        let token = FakeTokens::new(&server.url).token("a1");
+        let start = "2014-10-24T12:14:42-04:00"
+            .parse::<DateTime<Utc>>()
+            .unwrap();
+
+        let executions = client()
+            .account_executions(&token, "26598145", Some(&start), None)
+            .unwrap();
+        assert_eq!(Vec::<Execution>::new(), executions);
+        assert_eq!(
+            "/v1/accounts/26598145/executions?startTime=2014-10-24T16%3A14%3A42%2B00%3A00",
+            server.requests()[0].target
+        );
+    }
+}
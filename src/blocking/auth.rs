@@ -0,0 +1,235 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use url::Url;
+use uuid::Uuid;
+
+use super::Client;
+use crate::{
+    auth::{
+        self, answer_callback, loopback_addr, rotation, ApiToken, CallbackAnswer, CallbackRequest,
+        Rotation, Scope, TokenResponse, CALLBACK_READ_TIMEOUT,
+    },
+    errors::QuestradeError,
+    token::DEFAULT_REFRESH_MARGIN,
+};
+
+impl Client {
+    /// Exchanges a refresh token for a new [`ApiToken`], invalidating `refresh_token`. Refreshes
+    /// are coordinated through the token store like [`crate::Client::refresh_token`].
+    pub fn refresh_token(&self, refresh_token: &str) -> Result<ApiToken, QuestradeError> {
+        let _lock = self.lock_token_store()?;
+        let refresh_token =
+            match rotation(self.load_token()?, refresh_token, DEFAULT_REFRESH_MARGIN) {
+                Rotation::Reuse(stored) => return Ok(stored),
+                Rotation::Refresh(refresh_token) => refresh_token,
+            };
+
+        let params = [
+            ("client_id", self.consumer_key.clone()),
+            ("refresh_token", refresh_token),
+            ("grant_type", String::from("refresh_token")),
+        ];
+        let token: TokenResponse = self.send(
+            self.http
+                .request(reqwest::Method::POST, self.env.token_url()?)
+                .form(&params),
+        )?;
+        self.persist_token(token.into())
+    }
+
+    pub fn authorize_url(
+        &self,
+        redirect_uri: &str,
+        scopes: &[Scope],
+        state: &str,
+    ) -> Result<Url, QuestradeError> {
+        auth::authorize_url(&self.env, &self.consumer_key, redirect_uri, scopes, state)
+    }
+
+    pub fn exchange_code(
+        &self,
+        code: &str,
+        redirect_uri: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        let params = [
+            ("client_id", self.consumer_key.clone()),
+            ("code", String::from(code)),
+            ("grant_type", String::from("authorization_code")),
+            ("redirect_uri", String::from(redirect_uri)),
+        ];
+        let token: TokenResponse = self.send(
+            self.http
+                .request(reqwest::Method::POST, self.env.token_url()?)
+                .form(&params),
+        )?;
+        self.persist_token(token.into())
+    }
+
+    /// Runs the full authorization code flow, see [`crate::Client::login`].
+    pub fn login<F>(
+        &self,
+        redirect_uri: &str,
+        scopes: &[Scope],
+        open: F,
+    ) -> Result<ApiToken, QuestradeError>
+    where
+        F: FnOnce(&Url),
+    {
+        let redirect = Url::parse(redirect_uri)?;
+        let listener = TcpListener::bind(loopback_addr(&redirect)?)?;
+        let state = Uuid::new_v4().to_string();
+
+        open(&self.authorize_url(redirect_uri, scopes, &state)?);
+
+        let code = wait_for_callback(&listener, redirect.path(), &state, CALLBACK_READ_TIMEOUT)?;
+        self.exchange_code(&code, redirect_uri)
+    }
+}
+
+fn wait_for_callback(
+    listener: &TcpListener,
+    path: &str,
+    state: &str,
+    read_timeout: Duration,
+) -> Result<String, QuestradeError> {
+    loop {
+        let (mut stream, _) = listener.accept()?;
+        let request_line = match read_request_line(&mut stream, read_timeout) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::debug!("ignoring malformed callback request: {}", err);
+                continue;
+            }
+        };
+
+        let answer = answer_callback(&request_line, path, state);
+        respond(&mut stream, &answer);
+        if let Some(outcome) = answer.outcome {
+            return outcome;
+        }
+    }
+}
+
+/// Reads the request head, giving up once `read_timeout` has passed.
+fn read_request_line(
+    stream: &mut TcpStream,
+    read_timeout: Duration,
+) -> Result<String, QuestradeError> {
+    let deadline = Instant::now() + read_timeout;
+    let mut request = CallbackRequest::default();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let n = stream.read(&mut chunk)?;
+        if let Some(line) = request.push(&chunk[..n])? {
+            return Ok(line);
+        }
+    }
+}
+
+/// Answers a callback request. The browser may already have gone away, which is only logged.
+fn respond(stream: &mut TcpStream, answer: &CallbackAnswer) {
+    let written = stream
+        .write_all(answer.to_http().as_bytes())
+        .and_then(|_| stream.shutdown(std::net::Shutdown::Write));
+    if let Err(err) = written {
+        tracing::debug!("failed to answer callback request: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        blocking::client::tests::server,
+        store::{MemoryStore, TokenStore},
+        test_server::{test_blocking_client_builder, TestResponse},
+        Environment,
+    };
+
+    const TOKEN: &str = r#"{"access_token":"a1","token_type":"Bearer","expires_in":1800,"refresh_token":"r1","api_server":"https://api01.iq.questrade.com/"}"#;
+
+    #[test]
+    fn wait_for_callback_skips_idle_and_malformed_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _idle = TcpStream::connect(addr).unwrap();
+        drop(TcpStream::connect(addr).unwrap());
+        let mut callback = TcpStream::connect(addr).unwrap();
+        callback
+            .write_all(b"GET /callback?code=xyz&state=abc HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let code =
+            wait_for_callback(&listener, "/callback", "abc", Duration::from_millis(100)).unwrap();
+        assert_eq!("xyz", code);
+        let mut response = String::new();
+        callback.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    }
+
+    fn client(login_url: &str, store: Arc<MemoryStore>) -> Client {
+        test_blocking_client_builder()
+            .env(Environment::Custom {
+                login_url: Url::parse(login_url).unwrap(),
+            })
+            .token_store(store)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn refresh_token_persists_token() {
+        let (_runtime, server) = server(|_| TestResponse::json(200, TOKEN));
+        let store = Arc::new(MemoryStore::new());
+
+        let token = client(&server.url, store.clone())
+            .refresh_token("r0")
+            .unwrap();
+        assert_eq!("a1", token.access_token);
+        assert_eq!(Some(token), store.load().unwrap());
+
+        let request = &server.requests()[0];
+        assert_eq!("/oauth2/token", request.target);
+        assert_eq!(
+            "client_id=consumer&refresh_token=r0&grant_type=refresh_token",
+            request.body
+        );
+    }
+
+    #[test]
+    fn login_exchanges_code() {
+        let (_runtime, server) = server(|_| TestResponse::json(200, TOKEN));
+        let client = client(&server.url, Arc::new(MemoryStore::new()));
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+        let token = client
+            .login(&redirect_uri, &[Scope::ReadAccounts], |url| {
+                let state = url
+                    .query_pairs()
+                    .find(|(k, _)| k == "state")
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap();
+                let callback = format!("{}?code=xyz&state={}", redirect_uri, state);
+                std::thread::spawn(move || reqwest::blocking::get(callback).unwrap());
+            })
+            .unwrap();
+        assert_eq!("a1", token.access_token);
+        assert!(server.requests()[0].body.contains("code=xyz"));
+    }
+}
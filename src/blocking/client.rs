@@ -0,0 +1,318 @@
+use std::{sync::Arc, time::Instant};
+
+use reqwest::{
+    blocking::{Request, RequestBuilder},
+    Method,
+};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use crate::{
+    api_server::{ApiServerPolicy, Rewrite},
+    auth::ApiToken,
+    client::Time,
+    errors::QuestradeError,
+    rate_limit::{RateLimit, RateLimitCategory, RateLimiter},
+    response::{self, Response},
+    retry::{Attempts, Failure, RetryPolicy},
+    store::{self, StoreLock, TokenStore},
+    Environment,
+};
+
+/// The blocking counterpart of [`crate::Client`]. Requests are rate limited and retried the
+/// same way, but middlewares and token sources are only available on the async client.
+#[derive(Debug)]
+pub struct Client {
+    pub(crate) http: reqwest::blocking::Client,
+    pub(crate) env: Environment,
+    pub(crate) consumer_key: String,
+    pub(crate) token_store: Option<Arc<dyn TokenStore>>,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) api_server: ApiServerPolicy,
+}
+
+impl Client {
+    pub fn new(
+        http_client: reqwest::blocking::Client,
+        consumer_key: String,
+        env: Environment,
+    ) -> Result<Self, QuestradeError> {
+        Ok(Client {
+            http: http_client,
+            env,
+            consumer_key,
+            token_store: None,
+            rate_limiter: RateLimiter::new(),
+            retry_policy: RetryPolicy::none(),
+            api_server: ApiServerPolicy::default(),
+        })
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    /// The hourly budget the API last reported for `category`, if any request has been made.
+    pub fn rate_limit(&self, category: RateLimitCategory) -> Option<RateLimit> {
+        self.rate_limiter.current(category)
+    }
+
+    pub(crate) fn persist_token(&self, token: ApiToken) -> Result<ApiToken, QuestradeError> {
+        store::persist(self.token_store.as_deref(), token)
+    }
+
+    pub(crate) fn lock_token_store(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        match &self.token_store {
+            Some(store) => store.lock(),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn load_token(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        match &self.token_store {
+            Some(store) => store.load(),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn send<T>(&self, builder: RequestBuilder) -> Result<T, QuestradeError>
+    where
+        T: DeserializeOwned,
+    {
+        self.send_with_meta(builder).map(Response::into_data)
+    }
+
+    pub(crate) fn send_with_meta<T>(
+        &self,
+        builder: RequestBuilder,
+    ) -> Result<Response<T>, QuestradeError>
+    where
+        T: DeserializeOwned,
+    {
+        let request = builder.build()?;
+        self.api_server.check(request.url())?;
+        self.execute(request)
+    }
+
+    /// Sends `request`, retrying transient failures as allowed by the retry policy.
+    fn execute<T>(&self, mut request: Request) -> Result<Response<T>, QuestradeError>
+    where
+        T: DeserializeOwned,
+    {
+        let category = RateLimitCategory::from_url(request.url());
+        let mut attempts = Attempts::new(&self.retry_policy, request.method());
+        loop {
+            let retry = if attempts.may_retry() {
+                request.try_clone()
+            } else {
+                None
+            };
+            tracing::debug!(
+                method = %request.method(),
+                url = %request.url(),
+                attempt = attempts.attempt(),
+                max_attempts = attempts.max_attempts(),
+                "sending request"
+            );
+
+            self.rate_limiter.acquire_blocking(category);
+            let started = Instant::now();
+            let failure = match self.http.execute(request) {
+                Ok(response) => {
+                    self.rate_limiter.update(category, response.headers());
+                    match Self::decode(response, started) {
+                        Ok(response) => return Ok(response),
+                        Err(failure) => failure,
+                    }
+                }
+                Err(err) => Failure::transport(err.into()),
+            };
+
+            let (retry, delay) = attempts.retry(failure, retry)?;
+            request = retry;
+            std::thread::sleep(delay);
+        }
+    }
+
+    fn decode<T>(
+        response: reqwest::blocking::Response,
+        started: Instant,
+    ) -> Result<Response<T>, Failure>
+    where
+        T: DeserializeOwned,
+    {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let url = response.url().clone();
+        let decoded = match response.bytes() {
+            Ok(body) => response::decode(status, &headers, url, &body, started.elapsed()),
+            Err(err) => Err(err.into()),
+        };
+        decoded.map_err(|err| Failure::response(err, status, &headers))
+    }
+
+    pub(crate) fn base_request(
+        &self,
+        method: Method,
+        token: &ApiToken,
+        path: &str,
+    ) -> Result<RequestBuilder, QuestradeError> {
+        Ok(self
+            .http
+            .request(method, self.api_server.url(&token.api_server, path)?)
+            .bearer_auth(&token.access_token))
+    }
+
+    pub fn time(&self, token: &ApiToken) -> Result<Time, QuestradeError> {
+        self.time_with_meta(token).map(Response::into_data)
+    }
+
+    pub fn time_with_meta(&self, token: &ApiToken) -> Result<Response<Time>, QuestradeError> {
+        self.send_with_meta(self.base_request(Method::GET, token, "v1/time")?)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ClientBuilder {
+    http_client: Option<reqwest::blocking::Client>,
+    consumer_key: Option<String>,
+    env: Option<Environment>,
+    token_store: Option<Arc<dyn TokenStore>>,
+    retry_policy: Option<RetryPolicy>,
+    api_server: ApiServerPolicy,
+}
+
+impl ClientBuilder {
+    pub fn http_client(mut self, http_client: reqwest::blocking::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    pub fn consumer_key(mut self, consumer_key: String) -> Self {
+        self.consumer_key = Some(consumer_key);
+        self
+    }
+
+    pub fn env(mut self, env: Environment) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    pub fn token_store(mut self, token_store: Arc<dyn TokenStore>) -> Self {
+        self.token_store = Some(token_store);
+        self
+    }
+
+    /// How transient failures are retried, requests are sent once if this is not set.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Sends API requests to `url` instead of the `api_server` Questrade assigns with each token.
+    pub fn api_server(mut self, url: Url) -> Self {
+        self.api_server.rewrite = Some(Arc::new(move |_: &Url| url.clone()));
+        self
+    }
+
+    /// Rewrites the `api_server` of the token each API request is made with, e.g. to route API
+    /// requests through a proxy. Tokens themselves keep the `api_server` Questrade assigned.
+    /// Replaces [`ClientBuilder::api_server`].
+    pub fn rewrite_api_server<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&Url) -> Url + Send + Sync + 'static,
+    {
+        self.api_server.rewrite = Some(Arc::new(rewrite) as Rewrite);
+        self
+    }
+
+    /// Allows plain http login and API URLs, which are rejected by default. Meant for local test
+    /// servers.
+    pub fn allow_insecure(mut self, allow_insecure: bool) -> Self {
+        self.api_server.allow_insecure = allow_insecure;
+        self
+    }
+
+    pub fn build(self) -> Result<Client, QuestradeError> {
+        let http_client = self.http_client.ok_or_else(|| {
+            QuestradeError::Builder(String::from("http_client must be specified"))
+        })?;
+        let consumer_key = self.consumer_key.ok_or_else(|| {
+            QuestradeError::Builder(String::from("consumer_key must be specified"))
+        })?;
+        let env = self.env.unwrap_or(Environment::Production);
+        self.api_server.check(&env.host()?)?;
+
+        let mut client = Client::new(http_client, consumer_key, env)?;
+        client.token_store = self.token_store;
+        if let Some(retry_policy) = self.retry_policy {
+            client.retry_policy = retry_policy;
+        }
+        client.api_server = self.api_server;
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_server::{
+        test_blocking_client_builder, FakeTokens, RecordedRequest, TestResponse, TestServer,
+    };
+
+    /// Runs a [`TestServer`] on its own runtime so it can be called from blocking code. The
+    /// runtime has to be kept alive for as long as the server is used.
+    pub(crate) fn server<F>(handler: F) -> (tokio::runtime::Runtime, TestServer)
+    where
+        F: Fn(&RecordedRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime.block_on(TestServer::start(handler));
+        (runtime, server)
+    }
+
+    pub(crate) fn client() -> Client {
+        test_blocking_client_builder()
+            .retry_policy(RetryPolicy::default().initial_backoff(Duration::ZERO))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn time_works() {
+        let (_runtime, server) = server(|_| {
+            TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+                .header("X-RateLimit-Remaining", "29999")
+                .header("X-RateLimit-Reset", "1700000000")
+        });
+        let client = client();
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        let response = client.time_with_meta(&token).unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status);
+        assert_eq!(
+            29999,
+            client
+                .rate_limit(RateLimitCategory::Account)
+                .unwrap()
+                .remaining
+        );
+        let requests = server.requests();
+        assert_eq!("/v1/time", requests[0].target);
+        assert_eq!(Some("Bearer a1"), requests[0].header("authorization"));
+    }
+
+    #[test]
+    fn send_retries_transient_failures() {
+        let (_runtime, server) =
+            server(|_| TestResponse::json(503, r#"{"code": 1000, "message": "Unavailable"}"#));
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        let err = client().time(&token).unwrap_err();
+        assert!(matches!(err, QuestradeError::ApiError(e) if e.code == 1000));
+        assert_eq!(3, server.requests().len());
+    }
+}
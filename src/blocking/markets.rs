@@ -0,0 +1,145 @@
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::Deserialize;
+
+use super::Client;
+use crate::{
+    auth::ApiToken,
+    errors::QuestradeError,
+    markets::{Candle, Market, Quote},
+    response::Response,
+    Interval,
+};
+
+impl Client {
+    pub fn market_candles(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        interval: Interval,
+    ) -> Result<Vec<Candle>, QuestradeError> {
+        self.market_candles_with_meta(token, symbol_id, start, end, interval)
+            .map(Response::into_data)
+    }
+
+    pub fn market_candles_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        interval: Interval,
+    ) -> Result<Response<Vec<Candle>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub candles: Vec<Candle>,
+        }
+
+        let response: Response<Data> = self.send_with_meta(
+            self.base_request(
+                Method::GET,
+                token,
+                &format!("v1/markets/candles/{}", symbol_id),
+            )?
+            .query(&[
+                ("startTime", start.to_rfc3339()),
+                ("endTime", end.to_rfc3339()),
+                ("interval", interval.to_string()),
+            ]),
+        )?;
+        Ok(response.map(|data| data.candles))
+    }
+
+    pub fn market_quotes_symbol(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Vec<Quote>, QuestradeError> {
+        self.market_quotes_symbol_with_meta(token, symbol_id)
+            .map(Response::into_data)
+    }
+
+    pub fn market_quotes_symbol_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub quotes: Vec<Quote>,
+        }
+
+        let response: Response<Data> = self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/markets/quotes/{}", symbol_id),
+        )?)?;
+        Ok(response.map(|data| data.quotes))
+    }
+
+    pub fn market_quotes_symbols(
+        &self,
+        token: &ApiToken,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Vec<Quote>, QuestradeError> {
+        self.market_quotes_symbols_with_meta(token, symbol_ids)
+            .map(Response::into_data)
+    }
+
+    pub fn market_quotes_symbols_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub quotes: Vec<Quote>,
+        }
+
+        let ids: Vec<String> = symbol_ids.iter().map(|id| id.to_string()).collect();
+        let ids = ids.join(",");
+        let response: Response<Data> = self.send_with_meta(
+            self.base_request(Method::GET, token, "v1/markets/quotes")?
+                .query(&[("ids", ids)]),
+        )?;
+        Ok(response.map(|data| data.quotes))
+    }
+
+    pub fn markets(&self, token: &ApiToken) -> Result<Vec<Market>, QuestradeError> {
+        self.markets_with_meta(token).map(Response::into_data)
+    }
+
+    pub fn markets_with_meta(
+        &self,
+        token: &ApiToken,
+    ) -> Result<Response<Vec<Market>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub markets: Vec<Market>,
+        }
+
+        let response: Response<Data> =
+            self.send_with_meta(self.base_request(Method::GET, token, "v1/markets")?)?;
+        Ok(response.map(|data| data.markets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        blocking::client::tests::{client, server},
+        test_server::{FakeTokens, TestResponse},
+    };
+
+    #[test]
+    fn markets_works() {
+        let (_runtime, server) = server(|_| TestResponse::json(200, r#"{"markets": []}"#));
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        assert_eq!(Vec::<Market>::new(), client().markets(&token).unwrap());
+        assert_eq!("/v1/markets", server.requests()[0].target);
+    }
+}
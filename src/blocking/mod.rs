@@ -0,0 +1,13 @@
+//! A synchronous client for code that does not run an async runtime, built on
+//! [`reqwest::blocking`]. It shares its models and errors with the async [`crate::Client`].
+//!
+//! Like `reqwest::blocking`, it must not be used from within an async runtime.
+
+mod accounts;
+mod auth;
+mod client;
+mod markets;
+mod options;
+mod symbols;
+
+pub use client::{Client, ClientBuilder};
@@ -0,0 +1,33 @@
+use reqwest::Method;
+
+use super::Client;
+use crate::{
+    auth::ApiToken,
+    errors::QuestradeError,
+    options::{OptionChain, OptionChainData},
+    response::Response,
+};
+
+impl Client {
+    pub fn symbol_options(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<OptionChain, QuestradeError> {
+        self.symbol_options_with_meta(token, symbol_id)
+            .map(Response::into_data)
+    }
+
+    pub fn symbol_options_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<OptionChain>, QuestradeError> {
+        let response: Response<OptionChainData> = self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/symbols/{}/options", symbol_id),
+        )?)?;
+        Ok(response.map(|data| OptionChain::from(data.option_chain)))
+    }
+}
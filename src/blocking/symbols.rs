@@ -0,0 +1,117 @@
+use reqwest::Method;
+use serde::Deserialize;
+
+use super::Client;
+use crate::{
+    auth::ApiToken,
+    errors::QuestradeError,
+    response::Response,
+    symbols::{
+        query_lists, single_symbol, EquitySymbol, SearchPages, SymbolDetails, SymbolsData,
+        MAX_QUERY_LIST_LEN,
+    },
+};
+
+impl Client {
+    pub fn symbol(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<SymbolDetails, QuestradeError> {
+        self.symbol_with_meta(token, symbol_id)
+            .map(Response::into_data)
+    }
+
+    pub fn symbol_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<SymbolDetails>, QuestradeError> {
+        let response: Response<SymbolsData> = self.send_with_meta(self.base_request(
+            Method::GET,
+            token,
+            &format!("v1/symbols/{}", symbol_id),
+        )?)?;
+        single_symbol(response, symbol_id)
+    }
+
+    /// Details of each of `symbol_ids`. Long lists are fetched with several requests.
+    pub fn symbols_by_ids(
+        &self,
+        token: &ApiToken,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        let ids: Vec<String> = symbol_ids.iter().map(|id| id.to_string()).collect();
+        self.symbols_by("ids", token, &ids)
+    }
+
+    /// Details of each of `names`, e.g. `BMO.TO`. Long lists are fetched with several requests.
+    pub fn symbols_by_names(
+        &self,
+        token: &ApiToken,
+        names: Vec<String>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        self.symbols_by("names", token, &names)
+    }
+
+    fn symbols_by(
+        &self,
+        key: &str,
+        token: &ApiToken,
+        values: &[String],
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        let mut symbols = Vec::new();
+        for chunk in query_lists(values, MAX_QUERY_LIST_LEN) {
+            let data: SymbolsData = self.send(
+                self.base_request(Method::GET, token, "v1/symbols")?
+                    .query(&[(key, chunk)]),
+            )?;
+            symbols.extend(data.symbols);
+        }
+        Ok(symbols)
+    }
+
+    pub fn symbol_search(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        self.symbol_search_with_meta(token, prefix, offset)
+            .map(Response::into_data)
+    }
+
+    pub fn symbol_search_with_meta(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Response<Vec<EquitySymbol>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub symbols: Vec<EquitySymbol>,
+        }
+
+        let response: Response<Data> = self.send_with_meta(
+            self.base_request(Method::GET, token, "v1/symbols/search")?
+                .query(&[
+                    ("prefix", prefix.to_string()),
+                    ("offset", offset.to_string()),
+                ]),
+        )?;
+        Ok(response.map(|data| data.symbols))
+    }
+
+    /// Pages through [`Client::symbol_search`] until no new matches come back.
+    pub fn symbol_search_all(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        let mut pages = SearchPages::default();
+        while let Some(offset) = pages.next_offset() {
+            pages.add(self.symbol_search(token, prefix, offset)?);
+        }
+        Ok(pages.into_symbols())
+    }
+}
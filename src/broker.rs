@@ -0,0 +1,382 @@
+use std::{
+    fs, io,
+    os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::ApiToken,
+    client::Client,
+    errors::QuestradeError,
+    token::{TokenManager, TokenSource, DEFAULT_REFRESH_MARGIN},
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BrokerRequest {
+    Token,
+    Refresh { stale_access_token: String },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BrokerResponse {
+    Token(BrokerToken),
+    Error(String),
+}
+
+/// The part of an [`ApiToken`] handed out to other processes, the refresh token never leaves
+/// the broker.
+#[derive(Debug, Deserialize, Serialize)]
+struct BrokerToken {
+    access_token: String,
+    token_type: String,
+    api_server: String,
+    expires_in: usize,
+    issued_at: DateTime<Utc>,
+}
+
+impl From<ApiToken> for BrokerToken {
+    fn from(token: ApiToken) -> Self {
+        BrokerToken {
+            access_token: token.access_token,
+            token_type: token.token_type,
+            api_server: token.api_server,
+            expires_in: token.expires_in,
+            issued_at: token.issued_at,
+        }
+    }
+}
+
+impl From<BrokerToken> for ApiToken {
+    fn from(token: BrokerToken) -> Self {
+        ApiToken {
+            access_token: token.access_token,
+            token_type: token.token_type,
+            refresh_token: String::new(),
+            api_server: token.api_server,
+            expires_in: token.expires_in,
+            issued_at: token.issued_at,
+        }
+    }
+}
+
+/// Owns the refresh token for a login and serves access tokens to other processes over a Unix
+/// domain socket, so they never rotate the refresh token out from under each other.
+///
+/// The protocol is one JSON object per line, `{"type":"token"}` or
+/// `{"type":"refresh","stale_access_token":"..."}`, answered with `{"token":{...}}` or
+/// `{"error":"..."}`.
+#[derive(Debug)]
+pub struct TokenBroker {
+    client: Client,
+    tokens: TokenManager,
+}
+
+impl TokenBroker {
+    pub fn new(client: Client, tokens: TokenManager) -> Self {
+        TokenBroker { client, tokens }
+    }
+
+    /// Binds a listener at `path`, replacing a stale socket left behind by a previous run. Fails
+    /// if another broker is still listening there. The socket is only accessible by the current
+    /// user.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<UnixListener, QuestradeError> {
+        let path = path.as_ref();
+        check_not_listening(path)?;
+
+        // The socket is created with the umask's permissions. Bind it inside a private directory
+        // and only move it into place once restricted, so nobody can connect in between.
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let dir = path.with_file_name(format!(".{}.{}", file_name, Uuid::new_v4()));
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        let bound = bind_private(&dir.join("socket"), path);
+        if let Err(err) = fs::remove_dir_all(&dir) {
+            tracing::warn!("failed to remove {}: {}", dir.display(), err);
+        }
+        bound
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: UnixListener) -> Result<(), QuestradeError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let broker = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = broker.handle(stream).await {
+                    tracing::warn!("token broker connection failed: {}", err);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: UnixStream) -> Result<(), QuestradeError> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<BrokerRequest>(&line) {
+                Ok(request) => self.respond(request).await,
+                Err(err) => BrokerResponse::Error(format!("invalid request: {}", err)),
+            };
+            let mut response = serde_json::to_vec(&response)
+                .map_err(|err| QuestradeError::InternalError(err.to_string()))?;
+            response.push(b'\n');
+            writer.write_all(&response).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond(&self, request: BrokerRequest) -> BrokerResponse {
+        let token = match request {
+            BrokerRequest::Token => self.tokens.token(&self.client).await,
+            BrokerRequest::Refresh { stale_access_token } => {
+                self.tokens.refresh(&self.client, &stale_access_token).await
+            }
+        };
+        match token {
+            Ok(token) => BrokerResponse::Token(token.into()),
+            Err(err) => BrokerResponse::Error(err.to_string()),
+        }
+    }
+}
+
+/// Fails if `path` is anything but a socket nobody is listening on.
+fn check_not_listening(path: &Path) -> Result<(), QuestradeError> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )
+        .into());
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("a token broker is already listening at {}", path.display()),
+        )
+        .into()),
+        Err(_) => Ok(()),
+    }
+}
+
+fn bind_private(private: &Path, path: &Path) -> Result<UnixListener, QuestradeError> {
+    let listener = UnixListener::bind(private)?;
+    fs::set_permissions(private, fs::Permissions::from_mode(0o600))?;
+    fs::rename(private, path)?;
+    Ok(listener)
+}
+
+/// A [`TokenSource`] that asks a [`TokenBroker`] for tokens. Tokens are cached until they are
+/// about to expire, so most API calls do not touch the socket.
+///
+/// Tokens returned by this source have an empty `refresh_token`.
+#[derive(Debug)]
+pub struct BrokerTokenSource {
+    path: PathBuf,
+    cached: Mutex<Option<ApiToken>>,
+    refresh_margin: Duration,
+}
+
+impl BrokerTokenSource {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        BrokerTokenSource {
+            path: path.as_ref().to_path_buf(),
+            cached: Mutex::new(None),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+        }
+    }
+
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    async fn request(&self, request: BrokerRequest) -> Result<ApiToken, QuestradeError> {
+        let stream = UnixStream::connect(&self.path).await.map_err(|source| {
+            QuestradeError::TransportError {
+                broker: self.path.display().to_string(),
+                source,
+            }
+        })?;
+        let (reader, mut writer) = stream.into_split();
+
+        let mut line = serde_json::to_vec(&request)
+            .map_err(|err| QuestradeError::InternalError(err.to_string()))?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "token broker closed the connection",
+                )
+            })?;
+        let response: BrokerResponse = serde_json::from_str(&line).map_err(|err| {
+            QuestradeError::InternalError(format!("invalid token broker response: {}", err))
+        })?;
+
+        match response {
+            BrokerResponse::Token(token) => {
+                let token = ApiToken::from(token);
+                *self.cached.lock().unwrap() = Some(token.clone());
+                Ok(token)
+            }
+            BrokerResponse::Error(err) => Err(QuestradeError::AuthorizationError(format!(
+                "token broker failed: {}",
+                err
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenSource for BrokerTokenSource {
+    async fn token(&self, _client: &Client) -> Result<ApiToken, QuestradeError> {
+        if let Some(token) = self.cached.lock().unwrap().as_ref() {
+            if !token.expires_within(self.refresh_margin) {
+                return Ok(token.clone());
+            }
+        }
+        self.request(BrokerRequest::Token).await
+    }
+
+    async fn refresh(
+        &self,
+        _client: &Client,
+        stale_access_token: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        self.request(BrokerRequest::Refresh {
+            stale_access_token: String::from(stale_access_token),
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{test_client, test_token};
+
+    fn token(access_token: &str) -> ApiToken {
+        test_token(access_token, "https://api01.iq.questrade.com/")
+    }
+
+    #[tokio::test]
+    async fn broker_serves_tokens_without_refresh_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broker.sock");
+        let broker = Arc::new(TokenBroker::new(
+            test_client(),
+            TokenManager::new(token("a1")),
+        ));
+        let listener = TokenBroker::bind(&path).unwrap();
+        tokio::spawn(broker.serve(listener));
+
+        let source = BrokerTokenSource::new(&path);
+        let t = source.token(&test_client()).await.unwrap();
+        assert_eq!("a1", t.access_token);
+        assert_eq!("", t.refresh_token);
+        assert_eq!("https://api01.iq.questrade.com/", t.api_server);
+
+        // A refresh for a token the broker has already replaced returns the current one.
+        let t = source.refresh(&test_client(), "a0").await.unwrap();
+        assert_eq!("a1", t.access_token);
+    }
+
+    #[tokio::test]
+    async fn broker_token_source_caches_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broker.sock");
+        let broker = Arc::new(TokenBroker::new(
+            test_client(),
+            TokenManager::new(token("a1")),
+        ));
+        let listener = TokenBroker::bind(&path).unwrap();
+        let server = tokio::spawn(broker.serve(listener));
+
+        let source = BrokerTokenSource::new(&path);
+        source.token(&test_client()).await.unwrap();
+        server.abort();
+        let _ = server.await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            "a1",
+            source.token(&test_client()).await.unwrap().access_token
+        );
+        // The broker may be restarting, so failing to reach it is worth retrying.
+        let err = source.refresh(&test_client(), "a1").await.unwrap_err();
+        assert!(err.is_retryable(), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn bind_refuses_live_socket_and_replaces_stale_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broker.sock");
+
+        let listener = TokenBroker::bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        assert!(TokenBroker::bind(&path).is_err());
+
+        drop(listener);
+        TokenBroker::bind(&path).unwrap();
+        // Only the socket is left behind, not the private directory it was bound in.
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+
+        let file = dir.path().join("token.json");
+        fs::write(&file, "{}").unwrap();
+        assert!(TokenBroker::bind(&file).is_err());
+        assert_eq!("{}", fs::read_to_string(&file).unwrap());
+    }
+
+    #[tokio::test]
+    async fn invalid_broker_response_is_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broker.sock");
+        let listener = TokenBroker::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 64];
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut request).await;
+            stream.write_all(b"garbage\n").await.unwrap();
+        });
+
+        let err = BrokerTokenSource::new(&path)
+            .token(&test_client())
+            .await
+            .unwrap_err();
+        assert!(!err.is_retryable(), "{:?}", err);
+    }
+
+    #[test]
+    fn broker_protocol_serialize_works() {
+        let request = serde_json::to_string(&BrokerRequest::Refresh {
+            stale_access_token: "a1".into(),
+        })
+        .unwrap();
+        assert_eq!(r#"{"type":"refresh","stale_access_token":"a1"}"#, request);
+
+        let response: BrokerResponse = serde_json::from_str(r#"{"error":"nope"}"#).unwrap();
+        assert!(matches!(response, BrokerResponse::Error(e) if e == "nope"));
+    }
+}
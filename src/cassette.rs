@@ -0,0 +1,389 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    errors::QuestradeError,
+    middleware::{Middleware, Next},
+};
+
+const REDACTED: &str = "REDACTED";
+
+/// Form fields and JSON properties that hold credentials and are never written to a cassette or
+/// an error message.
+const SECRET_FIELDS: &[&str] = &["access_token", "client_id", "code", "refresh_token"];
+
+/// One request and the response it got, stored as a line of a cassette.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    /// Path and query of the request. The host is left out since `api_server` varies.
+    pub target: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub response: String,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record(Mutex<File>),
+    Replay(Mutex<VecDeque<Interaction>>),
+}
+
+/// A [`Middleware`] that records API traffic to a JSONL cassette, or serves a previously
+/// recorded cassette back without touching the network, for deterministic tests.
+///
+/// Access tokens, refresh tokens, authorization codes and the consumer key are redacted when
+/// recording. On replay, requests are matched on method and path and query, and each recorded
+/// interaction is served once.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+}
+
+impl Cassette {
+    /// Records to `path`, replacing any cassette already there.
+    pub fn record<P: AsRef<Path>>(path: P) -> Result<Self, QuestradeError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(Cassette {
+            path,
+            mode: Mode::Record(Mutex::new(file)),
+        })
+    }
+
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, QuestradeError> {
+        let path = path.as_ref().to_path_buf();
+        let interactions = fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<VecDeque<Interaction>, _>>()
+            .map_err(|err| {
+                QuestradeError::InternalError(format!(
+                    "invalid cassette {}: {}",
+                    path.display(),
+                    err
+                ))
+            })?;
+        Ok(Cassette {
+            path,
+            mode: Mode::Replay(Mutex::new(interactions)),
+        })
+    }
+
+    /// Records when `QT_RECORD` is set, replays otherwise.
+    pub fn from_env<P: AsRef<Path>>(path: P) -> Result<Self, QuestradeError> {
+        match std::env::var_os("QT_RECORD") {
+            Some(_) => Cassette::record(path),
+            None => Cassette::replay(path),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Interactions not yet served in replay mode.
+    pub fn remaining(&self) -> Vec<Interaction> {
+        match &self.mode {
+            Mode::Record(_) => Vec::new(),
+            Mode::Replay(interactions) => interactions.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+
+    async fn record_interaction(
+        &self,
+        file: &Mutex<File>,
+        request: Request,
+        next: Next<'_>,
+    ) -> Result<Response, QuestradeError> {
+        let method = request.method().to_string();
+        let target = target(request.url());
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|body| redact_form(&String::from_utf8_lossy(body)));
+
+        let response = next.run(request).await?;
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let bytes = response.bytes().await?;
+
+        let interaction = Interaction {
+            method,
+            target,
+            body,
+            status,
+            headers,
+            response: redact_json(&String::from_utf8_lossy(&bytes)),
+        };
+        let mut line = serde_json::to_vec(&interaction)
+            .map_err(|err| QuestradeError::InternalError(err.to_string()))?;
+        line.push(b'\n');
+        file.lock().unwrap().write_all(&line)?;
+
+        // The body was consumed above, so the caller gets the real, unredacted bytes back.
+        build_response(status, &interaction.headers, bytes.to_vec())
+    }
+
+    fn replay_interaction(
+        &self,
+        interactions: &Mutex<VecDeque<Interaction>>,
+        request: &Request,
+    ) -> Result<Response, QuestradeError> {
+        let method = request.method().as_str();
+        let target = target(request.url());
+        let mut interactions = interactions.lock().unwrap();
+        let index = interactions
+            .iter()
+            .position(|i| i.method == method && i.target == target)
+            .ok_or_else(|| {
+                QuestradeError::InternalError(format!(
+                    "no interaction for {} {} left in cassette {}",
+                    method,
+                    target,
+                    self.path.display()
+                ))
+            })?;
+        let interaction = interactions.remove(index).unwrap();
+        build_response(
+            interaction.status,
+            &interaction.headers,
+            interaction.response.into_bytes(),
+        )
+    }
+}
+
+#[async_trait]
+impl Middleware for Cassette {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, QuestradeError> {
+        match &self.mode {
+            Mode::Record(file) => self.record_interaction(file, request, next).await,
+            Mode::Replay(interactions) => self.replay_interaction(interactions, &request),
+        }
+    }
+}
+
+fn target(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn build_response(
+    status: u16,
+    headers: &[(String, String)],
+    body: Vec<u8>,
+) -> Result<Response, QuestradeError> {
+    let mut response = http::Response::builder().status(status);
+    for (name, value) in headers {
+        // The body is already decoded, so framing headers no longer apply.
+        if !name.eq_ignore_ascii_case("content-length")
+            && !name.eq_ignore_ascii_case("transfer-encoding")
+            && !name.eq_ignore_ascii_case("content-encoding")
+        {
+            response = response.header(name, value);
+        }
+    }
+    let response = response
+        .body(body)
+        .map_err(|err| QuestradeError::InternalError(err.to_string()))?;
+    Ok(Response::from(response))
+}
+
+fn redact_form(body: &str) -> String {
+    let pairs: Vec<(String, String)> = url::form_urlencoded::parse(body.as_bytes())
+        .map(|(k, v)| {
+            let v = if SECRET_FIELDS.contains(&k.as_ref()) {
+                REDACTED.into()
+            } else {
+                v.into_owned()
+            };
+            (k.into_owned(), v)
+        })
+        .collect();
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// Redacts credentials in a JSON body. Bodies that don't parse, such as truncated ones, have the
+/// string values of secret properties masked in place.
+pub(crate) fn redact_json(body: &str) -> String {
+    fn redact(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (k, v) in map.iter_mut() {
+                    if SECRET_FIELDS.contains(&k.as_str()) {
+                        *v = serde_json::Value::String(REDACTED.into());
+                    } else {
+                        redact(v);
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+            _ => {}
+        }
+    }
+
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => redact_text(body),
+    }
+}
+
+fn redact_text(body: &str) -> String {
+    let mut body = body.to_string();
+    for field in SECRET_FIELDS {
+        let key = format!("\"{}\"", field);
+        let mut from = 0;
+        while let Some(found) = body[from..].find(&key) {
+            let after_key = from + found + key.len();
+            from = after_key;
+            let rest = &body[after_key..];
+            let value = rest.trim_start();
+            let value = match value.strip_prefix(':') {
+                Some(value) => value.trim_start(),
+                None => continue,
+            };
+            if !value.starts_with('"') {
+                continue;
+            }
+            let start = body.len() - value.len() + 1;
+            let mut end = body.len();
+            let mut escaped = false;
+            for (i, c) in body[start..].char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = start + i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            body.replace_range(start..end, REDACTED);
+            from = start + REDACTED.len();
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        store::MemoryStore,
+        test_server::{test_client_builder, FakeTokens, TestResponse, TestServer},
+        Client, Environment,
+    };
+
+    const TOKEN: &str = r#"{"access_token":"a1","token_type":"Bearer","expires_in":1800,"refresh_token":"r1","api_server":"https://api01.iq.questrade.com/"}"#;
+
+    fn client(cassette: Cassette, login_url: &str) -> Client {
+        test_client_builder()
+            .env(Environment::Custom {
+                login_url: Url::parse(login_url).unwrap(),
+            })
+            .token_store(Arc::new(MemoryStore::new()))
+            .middleware(Arc::new(cassette))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cassette_records_then_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassettes/time.jsonl");
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/oauth2/token" => TestResponse::json(200, TOKEN),
+            _ => TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+                .header("X-RateLimit-Remaining", "29999")
+                .header("X-RateLimit-Reset", "1700000000"),
+        })
+        .await;
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        let recorder = client(Cassette::record(&path).unwrap(), &server.url);
+        let refreshed = recorder.refresh_token("r0").await.unwrap();
+        assert_eq!("r1", refreshed.refresh_token);
+        let recorded = recorder.time_with_meta(&token).await.unwrap();
+
+        let cassette = fs::read_to_string(&path).unwrap();
+        assert!(!cassette.contains("r0") && !cassette.contains("r1"));
+        assert!(!cassette.contains("consumer") && !cassette.contains("a1"));
+        assert_eq!(2, cassette.lines().count());
+
+        let replayer = client(Cassette::replay(&path).unwrap(), "http://127.0.0.1:1/");
+        let replayed = replayer.refresh_token("r0").await.unwrap();
+        assert_eq!(REDACTED, replayed.access_token);
+        let token = FakeTokens::new("http://127.0.0.1:1/").token("a1");
+        let replayed = replayer.time_with_meta(&token).await.unwrap();
+        assert_eq!(recorded.data, replayed.data);
+        assert_eq!(recorded.rate_limit, replayed.rate_limit);
+        assert_eq!(2, server.requests().len());
+
+        let err = replayer.time(&token).await.unwrap_err();
+        assert!(err.to_string().contains("no interaction for GET /v1/time"));
+    }
+
+    #[test]
+    fn redact_works() {
+        assert_eq!(
+            "client_id=REDACTED&grant_type=refresh_token&refresh_token=REDACTED",
+            redact_form("client_id=abc&grant_type=refresh_token&refresh_token=xyz")
+        );
+        assert_eq!(
+            r#"{"access_token":"REDACTED","accounts":[{"number":"1"}]}"#,
+            redact_json(r#"{"accounts":[{"number":"1"}],"access_token":"a1"}"#)
+        );
+        assert_eq!("not json", redact_json("not json"));
+        assert_eq!(
+            r#"{"refresh_token": "REDACTED", "access_token":"REDACTED"#,
+            redact_json(r#"{"refresh_token": "r\"1", "access_token":"a1"#)
+        );
+    }
+
+    #[test]
+    fn replay_rejects_invalid_cassette() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad.jsonl");
+        fs::write(&path, "{}\n").unwrap();
+        assert!(matches!(
+            Cassette::replay(&path),
+            Err(QuestradeError::InternalError(_))
+        ));
+    }
+}
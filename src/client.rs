@@ -1,20 +1,37 @@
+use std::{sync::Arc, time::Instant};
+
 use chrono::Utc;
-use reqwest::{Method, RequestBuilder};
+use reqwest::{header::AUTHORIZATION, Method, Request, RequestBuilder};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use url::Url;
 
 use crate::{
+    api_server::{ApiServerPolicy, Rewrite},
     auth::ApiToken,
-    errors::{ApiResponse, QuestradeError},
+    errors::QuestradeError,
+    middleware::{Middleware, Next},
+    rate_limit::{RateLimit, RateLimitCategory, RateLimiter},
+    response::{self, Response},
+    retry::{Attempts, Failure, RetryPolicy},
+    store::{self, StoreLock, TokenStore},
+    token::TokenSource,
     Environment,
 };
 
+#[derive(Debug)]
 pub struct Client {
     pub(crate) http: reqwest::Client,
     pub(crate) env: Environment,
     pub(crate) consumer_key: String,
+    pub(crate) token_store: Option<Arc<dyn TokenStore>>,
+    pub(crate) token_source: Option<Arc<dyn TokenSource>>,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) api_server: ApiServerPolicy,
+    pub(crate) middlewares: Vec<Arc<dyn Middleware>>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Time {
     pub time: chrono::DateTime<Utc>,
 }
@@ -29,13 +46,51 @@ impl Client {
             http: http_client,
             env,
             consumer_key,
+            token_store: None,
+            token_source: None,
+            rate_limiter: RateLimiter::new(),
+            retry_policy: RetryPolicy::none(),
+            api_server: ApiServerPolicy::default(),
+            middlewares: Vec::new(),
         })
     }
 
+    /// The hourly budget the API last reported for `category`, if any request has been made.
+    pub fn rate_limit(&self, category: RateLimitCategory) -> Option<RateLimit> {
+        self.rate_limiter.current(category)
+    }
+
     pub fn builder() -> ClientBuilder {
         ClientBuilder::default()
     }
 
+    /// Saves a freshly issued token to the configured store, if any. On failure the token is
+    /// returned inside the error so the caller can still persist it some other way.
+    pub(crate) fn persist_token(&self, token: ApiToken) -> Result<ApiToken, QuestradeError> {
+        store::persist(self.token_store.as_deref(), token)
+    }
+
+    /// Takes the token store lock, if any. It may wait on another process, so it is acquired on
+    /// a blocking thread.
+    pub(crate) async fn lock_token_store(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        match &self.token_store {
+            Some(store) => {
+                let store = store.clone();
+                tokio::task::spawn_blocking(move || store.lock())
+                    .await
+                    .map_err(|err| QuestradeError::InternalError(err.to_string()))?
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn load_token(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        match &self.token_store {
+            Some(store) => store.load(),
+            None => Ok(None),
+        }
+    }
+
     pub(crate) async fn send<T>(
         &self,
         builder: reqwest::RequestBuilder,
@@ -43,27 +98,151 @@ impl Client {
     where
         T: DeserializeOwned,
     {
-        let response = builder.send().await?.json::<ApiResponse<T>>().await?;
+        self.send_with_meta(builder).await.map(Response::into_data)
+    }
+
+    /// Sends a request and decodes the response. If a token source is configured and the
+    /// access token is rejected, GET requests are replayed once with a refreshed token.
+    pub(crate) async fn send_with_meta<T>(
+        &self,
+        builder: reqwest::RequestBuilder,
+    ) -> Result<Response<T>, QuestradeError>
+    where
+        T: DeserializeOwned,
+    {
+        let request = builder.build()?;
+        self.api_server.check(request.url())?;
+        let replay = match request.method() {
+            &Method::GET => request.try_clone(),
+            _ => None,
+        };
 
-        match response {
-            ApiResponse::Ok(data) => Ok(data),
-            ApiResponse::Err(err) => Err(QuestradeError::ApiError(err)),
+        match self.execute(request).await {
+            Err(err) if err.is_auth_error() => match (&self.token_source, replay) {
+                (Some(source), Some(replay)) => {
+                    tracing::debug!("access token rejected, refreshing and replaying: {}", err);
+                    let replay = self.reauthorize(source.as_ref(), replay).await?;
+                    self.execute(replay)
+                        .await
+                        .map_err(|err| QuestradeError::ReplayFailed(Box::new(err)))
+                }
+                _ => Err(err),
+            },
+            result => result,
         }
     }
 
+    /// Sends `request`, retrying transient failures as allowed by the retry policy.
+    async fn execute<T>(&self, mut request: Request) -> Result<Response<T>, QuestradeError>
+    where
+        T: DeserializeOwned,
+    {
+        let category = RateLimitCategory::from_url(request.url());
+        let mut attempts = Attempts::new(&self.retry_policy, request.method());
+        loop {
+            let retry = if attempts.may_retry() {
+                request.try_clone()
+            } else {
+                None
+            };
+            tracing::debug!(
+                method = %request.method(),
+                url = %request.url(),
+                attempt = attempts.attempt(),
+                max_attempts = attempts.max_attempts(),
+                "sending request"
+            );
+
+            self.rate_limiter.acquire(category).await;
+            let started = Instant::now();
+            let next = Next::new(&self.http, &self.middlewares);
+            let failure = match next.run(request).await {
+                Ok(response) => {
+                    self.rate_limiter.update(category, response.headers());
+                    match Self::decode(response, started).await {
+                        Ok(response) => return Ok(response),
+                        Err(failure) => failure,
+                    }
+                }
+                Err(err) => Failure::transport(err),
+            };
+
+            let (retry, delay) = attempts.retry(failure, retry)?;
+            request = retry;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn decode<T>(
+        response: reqwest::Response,
+        started: Instant,
+    ) -> Result<Response<T>, Failure>
+    where
+        T: DeserializeOwned,
+    {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let url = response.url().clone();
+        let decoded = match response.bytes().await {
+            Ok(body) => response::decode(status, &headers, url, &body, started.elapsed()),
+            Err(err) => Err(err.into()),
+        };
+        decoded.map_err(|err| Failure::response(err, status, &headers))
+    }
+
+    /// Refreshes the token a rejected request was sent with and points the request at the
+    /// refreshed token, including its `api_server`.
+    async fn reauthorize(
+        &self,
+        source: &dyn TokenSource,
+        mut request: Request,
+    ) -> Result<Request, QuestradeError> {
+        let stale_access_token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .to_string();
+        let token = source.refresh(self, &stale_access_token).await?;
+
+        // API paths all start with `v1/`, which separates them from the path of the base URL.
+        let url = request.url_mut();
+        let path = url
+            .path()
+            .rfind("/v1/")
+            .map(|start| url.path()[start + 1..].to_string())
+            .ok_or_else(|| {
+                QuestradeError::InternalError(format!("cannot replay request to {}", url))
+            })?;
+        let mut replay = self.api_server.url(&token.api_server, &path)?;
+        replay.set_query(url.query());
+        *url = replay;
+        let authorization = format!("Bearer {}", token.access_token)
+            .parse()
+            .map_err(|_| QuestradeError::InternalError(String::from("invalid access token")))?;
+        request.headers_mut().insert(AUTHORIZATION, authorization);
+        Ok(request)
+    }
+
     pub(crate) fn base_request(
         &self,
         method: Method,
         token: &ApiToken,
         path: &str,
-    ) -> RequestBuilder {
-        self.http
-            .request(method, format!("{}{}", token.api_server, path))
-            .bearer_auth(&token.access_token)
+    ) -> Result<RequestBuilder, QuestradeError> {
+        Ok(self
+            .http
+            .request(method, self.api_server.url(&token.api_server, path)?)
+            .bearer_auth(&token.access_token))
     }
 
     pub async fn time(&self, token: &ApiToken) -> Result<Time, QuestradeError> {
-        self.send(self.base_request(Method::GET, token, "v1/time"))
+        self.time_with_meta(token).await.map(Response::into_data)
+    }
+
+    pub async fn time_with_meta(&self, token: &ApiToken) -> Result<Response<Time>, QuestradeError> {
+        self.send_with_meta(self.base_request(Method::GET, token, "v1/time")?)
             .await
     }
 }
@@ -73,6 +252,11 @@ pub struct ClientBuilder {
     http_client: Option<reqwest::Client>,
     consumer_key: Option<String>,
     env: Option<Environment>,
+    token_store: Option<Arc<dyn TokenStore>>,
+    token_source: Option<Arc<dyn TokenSource>>,
+    retry_policy: Option<RetryPolicy>,
+    api_server: ApiServerPolicy,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
 impl ClientBuilder {
@@ -91,6 +275,53 @@ impl ClientBuilder {
         self
     }
 
+    pub fn token_store(mut self, token_store: Arc<dyn TokenStore>) -> Self {
+        self.token_store = Some(token_store);
+        self
+    }
+
+    /// Lets the client refresh rejected access tokens itself and replay the failed GET request.
+    pub fn token_source(mut self, token_source: Arc<dyn TokenSource>) -> Self {
+        self.token_source = Some(token_source);
+        self
+    }
+
+    /// How transient failures are retried, requests are sent once if this is not set.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Adds a middleware around every request. Middlewares run in the order they are added.
+    pub fn middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// Sends API requests to `url` instead of the `api_server` Questrade assigns with each token.
+    pub fn api_server(mut self, url: Url) -> Self {
+        self.api_server.rewrite = Some(Arc::new(move |_: &Url| url.clone()));
+        self
+    }
+
+    /// Rewrites the `api_server` of the token each API request is made with, e.g. to route API
+    /// requests through a proxy. Tokens themselves keep the `api_server` Questrade assigned.
+    /// Replaces [`ClientBuilder::api_server`].
+    pub fn rewrite_api_server<F>(mut self, rewrite: F) -> Self
+    where
+        F: Fn(&Url) -> Url + Send + Sync + 'static,
+    {
+        self.api_server.rewrite = Some(Arc::new(rewrite) as Rewrite);
+        self
+    }
+
+    /// Allows plain http login and API URLs, which are rejected by default. Meant for local test
+    /// servers.
+    pub fn allow_insecure(mut self, allow_insecure: bool) -> Self {
+        self.api_server.allow_insecure = allow_insecure;
+        self
+    }
+
     pub fn build(self) -> Result<Client, QuestradeError> {
         let http_client = self.http_client.ok_or_else(|| {
             QuestradeError::Builder(String::from("http_client must be specified"))
@@ -99,14 +330,305 @@ impl ClientBuilder {
             QuestradeError::Builder(String::from("consumer_key must be specified"))
         })?;
         let env = self.env.unwrap_or(Environment::Production);
+        self.api_server.check(&env.host()?)?;
 
-        Client::new(http_client, consumer_key, env)
+        let mut client = Client::new(http_client, consumer_key, env)?;
+        client.token_store = self.token_store;
+        client.token_source = self.token_source;
+        if let Some(retry_policy) = self.retry_policy {
+            client.retry_policy = retry_policy;
+        }
+        client.api_server = self.api_server;
+        client.middlewares = self.middlewares;
+        Ok(client)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server::{
+        test_client_builder, test_token, FakeTokens, TestResponse, TestServer,
+    };
+
+    const INVALID_TOKEN: &str = r#"{"code": 1017, "message": "Access token is invalid"}"#;
+
+    fn client(tokens: Arc<FakeTokens>) -> Client {
+        test_client_builder().token_source(tokens).build().unwrap()
+    }
+
+    fn retrying_client() -> Client {
+        test_client_builder()
+            .retry_policy(
+                RetryPolicy::default()
+                    .initial_backoff(std::time::Duration::from_millis(1))
+                    .jitter(false),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// Fails the first `failures` requests with `status`, then answers with the time.
+    async fn flaky_server(failures: usize, status: u16) -> TestServer {
+        let seen = std::sync::atomic::AtomicUsize::new(0);
+        TestServer::start(move |_| {
+            if seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+                TestResponse::json(status, r#"{"code": 1000, "message": "Unavailable"}"#)
+                    .header("Retry-After", "0")
+            } else {
+                TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn api_server_override_applies_to_every_request() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/oauth2/token" => TestResponse::json(
+                200,
+                r#"{"access_token":"a1","token_type":"Bearer","expires_in":1800,"refresh_token":"r1","api_server":"https://api01.iq.questrade.com/"}"#,
+            ),
+            _ => TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#),
+        })
+        .await;
+        let url = Url::parse(&server.url).unwrap();
+        let client = test_client_builder()
+            .env(Environment::Custom {
+                login_url: url.clone(),
+            })
+            .api_server(url.join("proxy").unwrap())
+            .build()
+            .unwrap();
+
+        // Issued tokens keep their api_server, the override applies when they are used.
+        let token = client.refresh_token("r0").await.unwrap();
+        assert_eq!("https://api01.iq.questrade.com/", token.api_server);
+        client.time(&token).await.unwrap();
+        // So does a token obtained elsewhere.
+        let token = test_token("a2", "https://api05.iq.questrade.com/");
+        client.time(&token).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!("POST", requests[0].method);
+        assert!(requests[0].body.contains("refresh_token=r0"));
+        assert_eq!("GET", requests[1].method);
+        assert_eq!("/proxy/v1/time", requests[1].target);
+        assert_eq!("/proxy/v1/time", requests[2].target);
+        assert_eq!(Some("Bearer a2"), requests[2].header("authorization"));
+    }
+
+    #[tokio::test]
+    async fn insecure_urls_are_rejected_by_default() {
+        let server = TestServer::start(|_| TestResponse::json(200, "{}")).await;
+        let builder = || {
+            Client::builder()
+                .http_client(reqwest::Client::new())
+                .consumer_key(String::from("consumer"))
+        };
+
+        let err = builder()
+            .env(Environment::Custom {
+                login_url: Url::parse(&server.url).unwrap(),
+            })
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, QuestradeError::InsecureUrl(_)));
+
+        let token = FakeTokens::new(&server.url).token("a1");
+        let err = builder().build().unwrap().time(&token).await.unwrap_err();
+        assert!(matches!(err, QuestradeError::InsecureUrl(_)));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_replays_get_with_refreshed_token() {
+        let old_server = TestServer::start(|_| TestResponse::json(401, INVALID_TOKEN)).await;
+        let new_server = TestServer::start(|request| match request.header("authorization") {
+            Some("Bearer a2") => {
+                TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+            }
+            _ => TestResponse::json(401, INVALID_TOKEN),
+        })
+        .await;
+        // The refreshed token's api_server has a path, as when served through a proxy.
+        let tokens = Arc::new(FakeTokens {
+            refreshed_api_server: format!("{}questrade", new_server.url),
+            ..FakeTokens::new(&old_server.url)
+        });
+        let client = client(tokens.clone());
+
+        client.time(&tokens.token("a1")).await.unwrap();
+        assert_eq!(1, tokens.refreshes());
+        assert_eq!(1, old_server.requests().len());
+        let replayed = new_server.requests();
+        assert_eq!(1, replayed.len());
+        assert_eq!("/questrade/v1/time", replayed[0].target);
+    }
+
+    #[tokio::test]
+    async fn send_reports_failed_replay() {
+        let server = TestServer::start(|_| TestResponse::json(401, INVALID_TOKEN)).await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let client = client(tokens.clone());
+
+        let err = client.time(&tokens.token("a1")).await.unwrap_err();
+        assert!(
+            matches!(&err, QuestradeError::ReplayFailed(inner) if inner.is_auth_error()),
+            "{:?}",
+            err
+        );
+        assert_eq!(1, tokens.refreshes());
+        assert_eq!(2, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_does_not_replay_non_idempotent_requests() {
+        let server = TestServer::start(|_| TestResponse::json(401, INVALID_TOKEN)).await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let client = client(tokens.clone());
+
+        let err = client
+            .send::<Time>(
+                client
+                    .base_request(Method::POST, &tokens.token("a1"), "v1/time")
+                    .unwrap(),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.is_auth_error());
+        assert_eq!(0, tokens.refreshes());
+        assert_eq!(1, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_retries_transient_failures() {
+        let server = flaky_server(2, 503).await;
+        let tokens = FakeTokens::new(&server.url);
+
+        retrying_client().time(&tokens.token("a1")).await.unwrap();
+        assert_eq!(3, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_gives_up_after_max_attempts() {
+        let server = flaky_server(5, 429).await;
+        let tokens = FakeTokens::new(&server.url);
+
+        let err = retrying_client()
+            .time(&tokens.token("a1"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, QuestradeError::ApiError(e) if e.code == 1000));
+        assert_eq!(3, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_gives_up_when_retry_after_exceeds_max_backoff() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(429, r#"{"code": 1006, "message": "Too many requests"}"#)
+                .header("Retry-After", "3600")
+        })
+        .await;
+        let tokens = FakeTokens::new(&server.url);
+
+        assert!(retrying_client().time(&tokens.token("a1")).await.is_err());
+        assert_eq!(1, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_retries_gateway_error_pages() {
+        let seen = std::sync::atomic::AtomicUsize::new(0);
+        let server = TestServer::start(move |_| {
+            match seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => TestResponse {
+                    status: 502,
+                    headers: vec![("Content-Type".into(), "text/html".into())],
+                    body: String::from("<html><h1>502 Bad Gateway</h1></html>"),
+                },
+                _ => TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#),
+            }
+        })
+        .await;
+        let tokens = FakeTokens::new(&server.url);
+
+        retrying_client().time(&tokens.token("a1")).await.unwrap();
+        assert_eq!(2, server.requests().len());
+
+        let server = TestServer::start(|_| TestResponse {
+            status: 502,
+            headers: vec![("Content-Type".into(), "text/html".into())],
+            body: String::from("<html><h1>502 Bad Gateway</h1></html>"),
+        })
+        .await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let err = client(tokens.clone())
+            .time(&tokens.token("a1"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            QuestradeError::HttpError { status, .. } if status.as_u16() == 502
+        ));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_does_not_retry_non_idempotent_or_client_errors() {
+        let server = flaky_server(5, 503).await;
+        let tokens = FakeTokens::new(&server.url);
+        let client = retrying_client();
+
+        let request = client
+            .base_request(Method::POST, &tokens.token("a1"), "v1/time")
+            .unwrap();
+        assert!(client.send::<Time>(request).await.is_err());
+        assert_eq!(1, server.requests().len());
+
+        let server = flaky_server(5, 400).await;
+        let tokens = FakeTokens::new(&server.url);
+        assert!(client.time(&tokens.token("a1")).await.is_err());
+        assert_eq!(1, server.requests().len());
+    }
+
+    #[tokio::test]
+    async fn send_tracks_rate_limit_headers() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+                .header("X-RateLimit-Remaining", "29999")
+                .header("X-RateLimit-Reset", "1700000000")
+        })
+        .await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let client = client(tokens.clone());
+        assert_eq!(None, client.rate_limit(RateLimitCategory::Account));
+
+        client.time(&tokens.token("a1")).await.unwrap();
+        let limit = client.rate_limit(RateLimitCategory::Account).unwrap();
+        assert_eq!(29999, limit.remaining);
+        assert_eq!(1700000000, limit.reset.timestamp());
+        assert_eq!(None, client.rate_limit(RateLimitCategory::MarketData));
+    }
+
+    #[tokio::test]
+    async fn send_with_meta_returns_response_details() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+                .header("X-RateLimit-Remaining", "29999")
+                .header("X-RateLimit-Reset", "1700000000")
+                .header("X-Request-Id", "abc")
+        })
+        .await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let client = client(tokens.clone());
+
+        let response = client.time_with_meta(&tokens.token("a1")).await.unwrap();
+        assert_eq!(reqwest::StatusCode::OK, response.status);
+        assert_eq!(format!("{}v1/time", server.url), response.url.as_str());
+        assert_eq!("abc", response.headers["x-request-id"]);
+        assert_eq!(29999, response.rate_limit.unwrap().remaining);
+        assert_eq!(2014, chrono::Datelike::year(&response.data.time));
+    }
 
     #[test]
     fn time_derserialize_works() {
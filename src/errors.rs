@@ -1,48 +1,264 @@
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use url::ParseError;
 
+use crate::auth::ApiToken;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiError {
     pub code: u32,
     pub message: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum ApiResponse<T> {
-    Ok(T),
-    Err(ApiError),
+impl ApiError {
+    pub fn error_code(&self) -> ApiErrorCode {
+        ApiErrorCode::from(self.code)
+    }
+}
+
+/// The error codes Questrade documents for its API. Codes not listed here are kept as
+/// [`ApiErrorCode::Unknown`]. That includes the order placement errors, such as a rejected order,
+/// since this client only reads orders and never places them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiErrorCode {
+    /// 1000, returned with a 5xx status.
+    InternalServerError,
+    /// 1001
+    InvalidEndpoint,
+    /// 1002
+    InvalidArgument,
+    /// 1003
+    ArgumentTooLong,
+    /// 1004
+    MissingArgument,
+    /// 1006, the per second or per hour request budget is exhausted.
+    RateLimitExceeded,
+    /// 1013, the request asked for a format other than JSON.
+    UnsupportedContentType,
+    /// 1017, the access token is invalid or expired. Also returned for rejected refresh tokens.
+    InvalidToken,
+    /// 1018, the token lacks the scope required by the endpoint.
+    Forbidden,
+    /// 1019, the requested entry, such as a symbol, account or order, does not exist.
+    NotFound,
+    Unknown(u32),
+}
+
+impl ApiErrorCode {
+    /// Whether sending the same request again later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ApiErrorCode::InternalServerError | ApiErrorCode::RateLimitExceeded
+        )
+    }
+}
+
+impl From<u32> for ApiErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            1000 => ApiErrorCode::InternalServerError,
+            1001 => ApiErrorCode::InvalidEndpoint,
+            1002 => ApiErrorCode::InvalidArgument,
+            1003 => ApiErrorCode::ArgumentTooLong,
+            1004 => ApiErrorCode::MissingArgument,
+            1006 => ApiErrorCode::RateLimitExceeded,
+            1013 => ApiErrorCode::UnsupportedContentType,
+            1017 => ApiErrorCode::InvalidToken,
+            1018 => ApiErrorCode::Forbidden,
+            1019 => ApiErrorCode::NotFound,
+            code => ApiErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl From<ApiErrorCode> for u32 {
+    fn from(code: ApiErrorCode) -> Self {
+        match code {
+            ApiErrorCode::InternalServerError => 1000,
+            ApiErrorCode::InvalidEndpoint => 1001,
+            ApiErrorCode::InvalidArgument => 1002,
+            ApiErrorCode::ArgumentTooLong => 1003,
+            ApiErrorCode::MissingArgument => 1004,
+            ApiErrorCode::RateLimitExceeded => 1006,
+            ApiErrorCode::UnsupportedContentType => 1013,
+            ApiErrorCode::InvalidToken => 1017,
+            ApiErrorCode::Forbidden => 1018,
+            ApiErrorCode::NotFound => 1019,
+            ApiErrorCode::Unknown(code) => code,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum QuestradeError {
     #[error("{0:?}")]
     ApiError(ApiError),
+    #[error("{0}")]
+    AuthorizationError(String),
     #[error("{0:?}")]
     Builder(String),
+    /// A response body that is not the JSON the endpoint was expected to return.
+    #[error("could not decode response from {endpoint} at {path}: {source}, body: {body}")]
+    DecodeError {
+        endpoint: String,
+        /// Where in the document decoding failed, e.g. `accounts[0].type`.
+        path: String,
+        /// The start of the response body.
+        body: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("{0}")]
+    DecryptionError(String),
+    /// An unsuccessful status without a Questrade error in the body, e.g. from a gateway.
+    #[error("{endpoint} returned {status}, body: {body}")]
+    HttpError {
+        status: StatusCode,
+        endpoint: String,
+        body: String,
+    },
+    #[error(
+        "refusing to use insecure url {0}, https is required unless insecure urls are allowed"
+    )]
+    InsecureUrl(String),
     #[error("{0}")]
     InternalError(String),
+    #[error("invalid option symbol: {0}")]
+    InvalidOptionSymbol(String),
+    #[error("{0}")]
+    IoError(#[from] std::io::Error),
+    #[error("request failed again after refreshing the access token: {0}")]
+    ReplayFailed(Box<QuestradeError>),
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
     #[error("{0}")]
-    TransportError(String),
-}
-
-impl From<reqwest::Error> for QuestradeError {
-    fn from(err: reqwest::Error) -> Self {
-        if err.is_connect()
-            || err.is_redirect()
-            || err.is_timeout()
-            || err.is_request()
-            || err.is_builder()
-        {
-            Self::TransportError(err.to_string())
-        } else {
-            Self::InternalError(err.to_string())
+    StoreError(String),
+    /// A successful response whose body is not JSON, e.g. a login page served by a proxy.
+    #[error("{endpoint} returned {content_type} instead of JSON, body: {body}")]
+    UnexpectedContentType {
+        endpoint: String,
+        content_type: String,
+        body: String,
+    },
+    #[error("token was refreshed but could not be persisted: {1}")]
+    UnpersistedToken(Box<ApiToken>, String),
+    /// A token broker could not be reached, e.g. because it is restarting.
+    #[error("failed to connect to token broker at {broker}: {source}")]
+    TransportError {
+        broker: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("no symbol matches {0}")]
+    UnresolvedSymbol(String),
+    #[error("invalid url: {0}")]
+    UrlError(#[from] ParseError),
+}
+
+impl QuestradeError {
+    /// The API error code, if the API rejected the request.
+    pub fn api_error_code(&self) -> Option<ApiErrorCode> {
+        match self {
+            QuestradeError::ApiError(err) => Some(err.error_code()),
+            _ => None,
+        }
+    }
+
+    /// Whether the API rejected the access token, meaning a refresh may help.
+    pub fn is_auth_error(&self) -> bool {
+        self.api_error_code() == Some(ApiErrorCode::InvalidToken)
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.api_error_code() == Some(ApiErrorCode::RateLimitExceeded)
+    }
+
+    /// Whether the request may succeed if sent again, because the connection failed or timed out,
+    /// or the API reported a transient error.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            QuestradeError::RequestError(err) => err.is_connect() || err.is_timeout(),
+            QuestradeError::HttpError { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            QuestradeError::TransportError { .. } => true,
+            err => err.api_error_code().is_some_and(|code| code.is_retryable()),
         }
     }
 }
 
-impl From<ParseError> for QuestradeError {
-    fn from(err: ParseError) -> Self {
-        Self::InternalError(err.to_string())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(code: u32) -> QuestradeError {
+        QuestradeError::ApiError(ApiError {
+            code,
+            message: String::new(),
+        })
+    }
+
+    fn io_error() -> QuestradeError {
+        QuestradeError::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+    }
+
+    fn transport_error() -> QuestradeError {
+        QuestradeError::TransportError {
+            broker: String::from("/run/questrade.sock"),
+            source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+        }
+    }
+
+    #[test]
+    fn api_error_code_round_trips() {
+        for code in [
+            1000, 1001, 1002, 1003, 1004, 1006, 1013, 1017, 1018, 1019, 4242,
+        ] {
+            assert_eq!(code, u32::from(ApiErrorCode::from(code)));
+        }
+        assert_eq!(ApiErrorCode::Unknown(4242), ApiErrorCode::from(4242));
+    }
+
+    #[test]
+    fn errors_are_classified() {
+        assert!(api_error(1017).is_auth_error());
+        assert!(!api_error(1017).is_retryable());
+        assert!(api_error(1006).is_rate_limited());
+        assert!(api_error(1006).is_retryable());
+        assert!(api_error(1000).is_retryable());
+        assert!(!api_error(1019).is_retryable());
+        assert!(transport_error().is_retryable());
+        assert!(!QuestradeError::InternalError(String::new()).is_retryable());
+        assert!(!io_error().is_retryable());
+        assert_eq!(None, io_error().api_error_code());
+    }
+
+    #[test]
+    fn errors_keep_their_source() {
+        use std::error::Error;
+
+        let err =
+            QuestradeError::from(reqwest::Client::new().get("not a url").build().unwrap_err());
+        assert!(!err.is_retryable());
+        assert!(err.source().is_some());
+
+        let err = QuestradeError::from(url::Url::parse("not a url").unwrap_err());
+        assert!(matches!(err, QuestradeError::UrlError(_)));
+        assert!(err.source().is_some());
+
+        let io_kind = |err: &QuestradeError| {
+            err.source()
+                .and_then(|source| source.downcast_ref::<std::io::Error>())
+                .map(|source| source.kind())
+        };
+        assert_eq!(
+            Some(std::io::ErrorKind::PermissionDenied),
+            io_kind(&io_error())
+        );
+        assert_eq!(
+            Some(std::io::ErrorKind::ConnectionRefused),
+            io_kind(&transport_error())
+        );
     }
 }
@@ -3,13 +3,35 @@ use serde::{Deserialize, Serialize};
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
 
 pub mod accounts;
+mod api_server;
 pub mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(unix)]
+pub mod broker;
+pub mod cassette;
 pub mod client;
 pub mod errors;
 pub mod markets;
+pub mod middleware;
+#[cfg(feature = "mock-server")]
+pub mod mock;
+pub mod options;
+pub mod rate_limit;
+pub mod resolver;
+pub mod response;
+pub mod retry;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod session;
+pub mod store;
 pub mod symbols;
+#[cfg(any(test, feature = "mock-server"))]
+mod test_server;
+pub mod token;
 
 pub use client::Client;
+pub use session::Session;
 use url::Url;
 
 #[derive(Debug, strum_macros::Display, Serialize, Deserialize)]
@@ -18,23 +40,36 @@ use url::Url;
 pub enum Environment {
     Practice,
     Production,
+    /// A login host other than Questrade's, such as a proxy or a local test server. OAuth paths
+    /// are joined onto `login_url`, so a path prefix such as `https://proxy.corp/questrade/` is
+    /// kept. Plain http is only accepted by clients built with `allow_insecure(true)`.
+    Custom {
+        login_url: Url,
+    },
 }
 
 impl Environment {
+    /// The login base URL. It always ends in `/`, so OAuth paths extend rather than replace its
+    /// last segment.
     fn host(&self) -> Result<Url, QuestradeError> {
-        Ok(match self {
-            Environment::Practice => Url::parse("https://practicelogin.questrade.com")?,
-            Environment::Production => Url::parse("https://login.questrade.com")?,
-        })
+        let mut url = match self {
+            Environment::Practice => Url::parse("https://practicelogin.questrade.com/")?,
+            Environment::Production => Url::parse("https://login.questrade.com/")?,
+            Environment::Custom { login_url } => login_url.clone(),
+        };
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Ok(url)
     }
 
-    #[allow(dead_code)]
     fn authorize_url(&self) -> Result<Url, QuestradeError> {
-        Ok(self.host()?.join("/oauth2/authorize")?)
+        Ok(self.host()?.join("oauth2/authorize")?)
     }
 
     fn token_url(&self) -> Result<Url, QuestradeError> {
-        Ok(self.host()?.join("/oauth2/token")?)
+        Ok(self.host()?.join("oauth2/token")?)
     }
 }
 
@@ -104,7 +139,15 @@ pub enum ClientAccountType {
 }
 
 #[derive(
-    Debug, strum_macros::Display, strum_macros::EnumIter, Deserialize, Serialize, PartialEq, Clone,
+    Debug,
+    strum_macros::Display,
+    strum_macros::EnumIter,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
 )]
 pub enum Currency {
     CAD,
@@ -126,6 +169,107 @@ pub enum ActivityType {
     Other(String),
 }
 
+#[derive(
+    Debug,
+    strum_macros::EnumIter,
+    Deserialize_enum_str,
+    Serialize_enum_str,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+)]
+pub enum SecurityType {
+    Stock,
+    Option,
+    Bond,
+    Right,
+    Gold,
+    MutualFund,
+    Index,
+    #[serde(other)]
+    Other(String),
+}
+
+#[derive(
+    Debug,
+    strum_macros::EnumIter,
+    Deserialize_enum_str,
+    Serialize_enum_str,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+)]
+pub enum ListingExchange {
+    TSX,
+    TSXV,
+    CNSX,
+    MX,
+    NASDAQ,
+    NYSE,
+    NYSEAM,
+    ARCA,
+    OPRA,
+    PinkSheets,
+    OTCBB,
+    #[serde(other)]
+    Other(String),
+}
+
+#[derive(
+    Debug,
+    strum_macros::Display,
+    strum_macros::EnumIter,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+    Copy,
+)]
+pub enum OptionType {
+    Call,
+    Put,
+}
+
+#[derive(
+    Debug,
+    strum_macros::EnumIter,
+    Deserialize_enum_str,
+    Serialize_enum_str,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+)]
+pub enum OptionDurationType {
+    Weekly,
+    Monthly,
+    Quarterly,
+    LEAP,
+    #[serde(other)]
+    Other(String),
+}
+
+#[derive(
+    Debug,
+    strum_macros::EnumIter,
+    Deserialize_enum_str,
+    Serialize_enum_str,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+)]
+pub enum OptionExerciseType {
+    American,
+    European,
+    #[serde(other)]
+    Other(String),
+}
+
 #[derive(
     Debug, strum_macros::Display, strum_macros::EnumIter, Deserialize, Serialize, PartialEq, Clone,
 )]
@@ -161,6 +305,30 @@ pub enum Interval {
 mod tests {
     use super::*;
 
+    #[test]
+    fn environment_urls_keep_login_url_prefix() {
+        assert_eq!(
+            "https://login.questrade.com/oauth2/token",
+            Environment::Production.token_url().unwrap().as_str()
+        );
+        for login_url in [
+            "https://proxy.corp/questrade/",
+            "https://proxy.corp/questrade",
+        ] {
+            let env = Environment::Custom {
+                login_url: Url::parse(login_url).unwrap(),
+            };
+            assert_eq!(
+                "https://proxy.corp/questrade/oauth2/authorize",
+                env.authorize_url().unwrap().as_str()
+            );
+            assert_eq!(
+                "https://proxy.corp/questrade/oauth2/token",
+                env.token_url().unwrap().as_str()
+            );
+        }
+    }
+
     #[test]
     fn account_type_display_works() {
         <AccountType as strum::IntoEnumIterator>::iter().for_each(|t| {
@@ -230,6 +398,17 @@ mod tests {
         })
     }
 
+    #[test]
+    fn option_type_display_works() {
+        <OptionType as strum::IntoEnumIterator>::iter().for_each(|t| {
+            let expected_string = match t {
+                OptionType::Call => "Call",
+                OptionType::Put => "Put",
+            };
+            assert_eq!(expected_string, format!("{}", t));
+        })
+    }
+
     #[test]
     fn state_filter_display_works() {
         <StateFilter as strum::IntoEnumIterator>::iter().for_each(|f| {
@@ -2,7 +2,7 @@ use chrono::{DateTime, Utc};
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
-use crate::{auth::ApiToken, errors::QuestradeError, Client, Interval};
+use crate::{auth::ApiToken, errors::QuestradeError, response::Response, Client, Interval};
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -66,18 +66,31 @@ impl Client {
         end: &DateTime<Utc>,
         interval: Interval,
     ) -> Result<Vec<Candle>, QuestradeError> {
+        self.market_candles_with_meta(token, symbol_id, start, end, interval)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn market_candles_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        interval: Interval,
+    ) -> Result<Response<Vec<Candle>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Data {
             pub candles: Vec<Candle>,
         }
 
-        let data: Data = self
-            .send(
+        let response: Response<Data> = self
+            .send_with_meta(
                 self.base_request(
                     Method::GET,
                     token,
                     &format!("v1/markets/candles/{}", symbol_id),
-                )
+                )?
                 .query(&[
                     ("startTime", start.to_rfc3339()),
                     ("endTime", end.to_rfc3339()),
@@ -85,7 +98,7 @@ impl Client {
                 ]),
             )
             .await?;
-        Ok(data.candles)
+        Ok(response.map(|data| data.candles))
     }
 
     pub async fn market_quotes_symbol(
@@ -93,19 +106,29 @@ impl Client {
         token: &ApiToken,
         symbol_id: i64,
     ) -> Result<Vec<Quote>, QuestradeError> {
+        self.market_quotes_symbol_with_meta(token, symbol_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn market_quotes_symbol_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Data {
             pub quotes: Vec<Quote>,
         }
 
-        let data: Data = self
-            .send(self.base_request(
+        let response: Response<Data> = self
+            .send_with_meta(self.base_request(
                 Method::GET,
                 token,
                 &format!("v1/markets/quotes/{}", symbol_id),
-            ))
+            )?)
             .await?;
-        Ok(data.quotes)
+        Ok(response.map(|data| data.quotes))
     }
 
     pub async fn market_quotes_symbols(
@@ -113,30 +136,49 @@ impl Client {
         token: &ApiToken,
         symbol_ids: Vec<i64>,
     ) -> Result<Vec<Quote>, QuestradeError> {
+        self.market_quotes_symbols_with_meta(token, symbol_ids)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn market_quotes_symbols_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Data {
             pub quotes: Vec<Quote>,
         }
 
-        let data: Data = self
-            .send(
-                self.base_request(Method::GET, token, &format!("v1/markets/quotes"))
-                    .query(&[("ids", symbol_ids)]),
+        let ids: Vec<String> = symbol_ids.iter().map(|id| id.to_string()).collect();
+        let ids = ids.join(",");
+        let response: Response<Data> = self
+            .send_with_meta(
+                self.base_request(Method::GET, token, "v1/markets/quotes")?
+                    .query(&[("ids", ids)]),
             )
             .await?;
-        Ok(data.quotes)
+        Ok(response.map(|data| data.quotes))
     }
 
     pub async fn markets(&self, token: &ApiToken) -> Result<Vec<Market>, QuestradeError> {
+        self.markets_with_meta(token).await.map(Response::into_data)
+    }
+
+    pub async fn markets_with_meta(
+        &self,
+        token: &ApiToken,
+    ) -> Result<Response<Vec<Market>>, QuestradeError> {
         #[derive(Deserialize)]
         pub struct Data {
             pub markets: Vec<Market>,
         }
 
-        let data: Data = self
-            .send(self.base_request(Method::GET, token, &format!("v1/markets")))
+        let response: Response<Data> = self
+            .send_with_meta(self.base_request(Method::GET, token, "v1/markets")?)
             .await?;
-        Ok(data.markets)
+        Ok(response.map(|data| data.markets))
     }
 }
 
@@ -179,7 +221,7 @@ mod tests {
             volume: 983609,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let account = d.candles.get(0).unwrap();
+        let account = d.candles.first().unwrap();
         assert_eq!(expected, account)
     }
 
@@ -258,7 +300,7 @@ mod tests {
             snap_quotes_limit: 99999,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let account = d.markets.get(0).unwrap();
+        let account = d.markets.first().unwrap();
         assert_eq!(expected, account)
     }
 
@@ -317,7 +359,7 @@ mod tests {
             is_halted: false,
         };
         let d: Data = serde_json::from_str(data).expect("failed to deserialize JSON");
-        let account = d.quotes.get(0).unwrap();
+        let account = d.quotes.first().unwrap();
         assert_eq!(expected, account)
     }
 }
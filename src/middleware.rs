@@ -0,0 +1,191 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use reqwest::{Request, Response};
+
+use crate::errors::QuestradeError;
+
+/// A layer around every HTTP request the [`Client`](crate::Client) sends to the API. A
+/// middleware may change the request, short-circuit it with its own response or error, or
+/// inspect what comes back from `next`.
+///
+/// Middlewares run in the order they were added to the builder, once per attempt, inside retries
+/// and rate limiting.
+#[async_trait]
+pub trait Middleware: Debug + Send + Sync {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, QuestradeError>;
+}
+
+/// The rest of the middleware chain, ending with the HTTP client itself.
+#[derive(Debug, Clone, Copy)]
+pub struct Next<'a> {
+    http: &'a reqwest::Client,
+    middlewares: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(http: &'a reqwest::Client, middlewares: &'a [Arc<dyn Middleware>]) -> Self {
+        Next { http, middlewares }
+    }
+
+    pub async fn run(self, request: Request) -> Result<Response, QuestradeError> {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                middleware.handle(request, Next::new(self.http, rest)).await
+            }
+            None => Ok(self.http.execute(request).await?),
+        }
+    }
+}
+
+/// Logs each request and its outcome at `info`, or `warn` on failure. Headers are not logged
+/// since they carry the access token.
+#[derive(Debug, Default)]
+pub struct LoggingMiddleware;
+
+#[async_trait]
+impl Middleware for LoggingMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, QuestradeError> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+        tracing::info!(%method, %url, "questrade request");
+        let result = next.run(request).await;
+        match &result {
+            Ok(response) => {
+                tracing::info!(%method, %url, status = %response.status(), "questrade response")
+            }
+            Err(err) => tracing::warn!(%method, %url, error = %err, "questrade request failed"),
+        }
+        result
+    }
+}
+
+/// Aggregate timings collected by a [`TimingMiddleware`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub requests: u64,
+    pub failures: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+/// Measures how long each request takes until its response headers arrive. Keep an `Arc` to
+/// read the totals with [`TimingMiddleware::timings`].
+#[derive(Debug, Default)]
+pub struct TimingMiddleware {
+    timings: Mutex<Timings>,
+}
+
+impl TimingMiddleware {
+    pub fn new() -> Self {
+        TimingMiddleware::default()
+    }
+
+    pub fn timings(&self) -> Timings {
+        *self.timings.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Middleware for TimingMiddleware {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, QuestradeError> {
+        let url = request.url().clone();
+        let started = Instant::now();
+        let result = next.run(request).await;
+        let elapsed = started.elapsed();
+        tracing::debug!(%url, ?elapsed, "questrade request timing");
+
+        let mut timings = self.timings.lock().unwrap();
+        timings.requests += 1;
+        if !matches!(&result, Ok(response) if response.status().is_success()) {
+            timings.failures += 1;
+        }
+        timings.total += elapsed;
+        timings.max = timings.max.max(elapsed);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        retry::RetryPolicy,
+        test_server::{test_client_builder, FakeTokens, TestResponse, TestServer},
+    };
+
+    #[derive(Debug)]
+    struct Header;
+
+    #[async_trait]
+    impl Middleware for Header {
+        async fn handle(
+            &self,
+            mut request: Request,
+            next: Next<'_>,
+        ) -> Result<Response, QuestradeError> {
+            request
+                .headers_mut()
+                .insert("x-audit", "middleware".parse().unwrap());
+            next.run(request).await
+        }
+    }
+
+    /// Answers the first request with a 503 without reaching the server.
+    #[derive(Debug, Default)]
+    struct FailOnce(AtomicUsize);
+
+    #[async_trait]
+    impl Middleware for FailOnce {
+        async fn handle(
+            &self,
+            request: Request,
+            next: Next<'_>,
+        ) -> Result<Response, QuestradeError> {
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                let response = http::Response::builder()
+                    .status(503)
+                    .body(r#"{"code": 1000, "message": "Injected"}"#)
+                    .unwrap();
+                return Ok(Response::from(response));
+            }
+            next.run(request).await
+        }
+    }
+
+    #[tokio::test]
+    async fn middlewares_wrap_each_attempt() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+        })
+        .await;
+        let timing = Arc::new(TimingMiddleware::new());
+        let client = test_client_builder()
+            .retry_policy(RetryPolicy::default().initial_backoff(Duration::ZERO))
+            .middleware(Arc::new(LoggingMiddleware))
+            .middleware(timing.clone())
+            .middleware(Arc::new(FailOnce::default()))
+            .middleware(Arc::new(Header))
+            .build()
+            .unwrap();
+
+        client
+            .time(&FakeTokens::new(&server.url).token("a1"))
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(1, requests.len());
+        assert_eq!(Some("middleware"), requests[0].header("x-audit"));
+        let timings = timing.timings();
+        assert_eq!(2, timings.requests);
+        assert_eq!(1, timings.failures);
+        assert!(timings.max <= timings.total);
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    accounts::{Account, Activity, Balances, Execution, Order, Position},
+    client::ClientBuilder,
+    markets::{Candle, Market, Quote},
+    options::OptionChain,
+    symbols::{EquitySymbol, SymbolDetails},
+    test_server::{RecordedRequest, TestResponse, TestServer},
+    Client, Environment,
+};
+
+/// Data served by a [`MockServer`], keyed by account number or symbol id where the API is.
+#[derive(Debug, Clone, Default)]
+pub struct Fixtures {
+    pub accounts: Vec<Account>,
+    pub activities: HashMap<String, Vec<Activity>>,
+    pub balances: HashMap<String, Balances>,
+    pub positions: HashMap<String, Vec<Position>>,
+    pub executions: HashMap<String, Vec<Execution>>,
+    pub orders: HashMap<String, Vec<Order>>,
+    pub markets: Vec<Market>,
+    pub quotes: Vec<Quote>,
+    pub candles: HashMap<i64, Vec<Candle>>,
+    pub symbols: Vec<EquitySymbol>,
+    pub symbol_details: Vec<SymbolDetails>,
+    pub option_chains: HashMap<i64, OptionChain>,
+}
+
+/// An error a [`MockServer`] returns instead of handling the next API request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// 401 with code 1017, as for an expired or revoked access token.
+    ExpiredToken,
+    /// 429 with code 1006 and an exhausted `X-RateLimit-Remaining`.
+    RateLimited,
+    /// 500 with code 1000.
+    ServerError,
+}
+
+#[derive(Debug)]
+struct State {
+    fixtures: Fixtures,
+    faults: VecDeque<Fault>,
+    api_server: String,
+    issued: u32,
+    access_token: Option<String>,
+    refresh_token: String,
+}
+
+/// A local stand-in for the Questrade login and API servers for integration tests.
+///
+/// It serves `/oauth2/token`, rotating the refresh token like Questrade does and returning its
+/// own URL as `api_server`, and the `v1/time`, `v1/accounts/*`, `v1/markets/*` and
+/// `v1/symbols/*` endpoints from [`Fixtures`]. API requests must carry the access token most
+/// recently issued. Clients built from [`MockServer::client_builder`] log in against it.
+pub struct MockServer {
+    server: TestServer,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockServer")
+            .field("url", &self.server.url)
+            .finish()
+    }
+}
+
+impl MockServer {
+    /// Starts the server on a random loopback port. Must be called within a Tokio runtime.
+    pub async fn start(fixtures: Fixtures) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            fixtures,
+            faults: VecDeque::new(),
+            api_server: String::new(),
+            issued: 0,
+            access_token: None,
+            refresh_token: String::from("mock-refresh-0"),
+        }));
+        let handler_state = state.clone();
+        let server = TestServer::start(move |request| {
+            let mut state = handler_state.lock().unwrap();
+            handle(&mut state, request)
+        })
+        .await;
+        state.lock().unwrap().api_server = server.url.clone();
+        MockServer { server, state }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&self.server.url).unwrap()
+    }
+
+    /// An environment whose login host is this server.
+    pub fn environment(&self) -> Environment {
+        Environment::Custom {
+            login_url: self.url(),
+        }
+    }
+
+    /// A client builder whose login host is this server. It allows insecure URLs, since the
+    /// server speaks plain http.
+    pub fn client_builder(&self) -> ClientBuilder {
+        Client::builder()
+            .env(self.environment())
+            .allow_insecure(true)
+    }
+
+    /// The refresh token the server currently accepts.
+    pub fn refresh_token(&self) -> String {
+        self.state.lock().unwrap().refresh_token.clone()
+    }
+
+    /// Invalidates the current access token, as if it had expired. The refresh token stays
+    /// valid.
+    pub fn expire_access_token(&self) {
+        self.state.lock().unwrap().access_token = None;
+    }
+
+    /// Queues `fault` to be returned for the next API request, faults are served in order.
+    pub fn fail_next(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    pub fn update_fixtures<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Fixtures),
+    {
+        f(&mut self.state.lock().unwrap().fixtures)
+    }
+
+    /// Number of requests received so far, including token requests.
+    pub fn request_count(&self) -> usize {
+        self.server.requests().len()
+    }
+}
+
+fn error(status: u16, code: u32, message: &str) -> TestResponse {
+    TestResponse::json(
+        status,
+        &json!({ "code": code, "message": message }).to_string(),
+    )
+}
+
+fn ok<T: Serialize>(body: T) -> TestResponse {
+    TestResponse::json(200, &serde_json::to_string(&body).unwrap())
+}
+
+fn for_account<T: Clone>(map: &HashMap<String, Vec<T>>, id: &str) -> Vec<T> {
+    map.get(id).cloned().unwrap_or_default()
+}
+
+fn handle(state: &mut State, request: &RecordedRequest) -> TestResponse {
+    let url = Url::parse("http://localhost")
+        .unwrap()
+        .join(&request.target)
+        .unwrap();
+    if url.path() == "/oauth2/token" {
+        return token(state, request);
+    }
+
+    if let Some(fault) = state.faults.pop_front() {
+        return match fault {
+            Fault::ExpiredToken => error(401, 1017, "Access token is invalid"),
+            Fault::RateLimited => error(429, 1006, "Rate limit exceeded")
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", &Utc::now().timestamp().to_string()),
+            Fault::ServerError => error(500, 1000, "Internal server error"),
+        };
+    }
+
+    let authorized = match (&state.access_token, request.header("authorization")) {
+        (Some(token), Some(header)) => header.strip_prefix("Bearer ") == Some(token.as_str()),
+        _ => false,
+    };
+    if !authorized {
+        return error(401, 1017, "Access token is invalid");
+    }
+    if request.method != "GET" {
+        return error(405, 1001, "Invalid endpoint");
+    }
+
+    let segments: Vec<&str> = url.path().trim_matches('/').split('/').collect();
+    let fixtures = &state.fixtures;
+    match segments.as_slice() {
+        ["v1", "time"] => ok(json!({ "time": Utc::now() })),
+        ["v1", "accounts"] => ok(json!({ "accounts": fixtures.accounts, "userId": 1 })),
+        ["v1", "accounts", id, "activities"] => {
+            ok(json!({ "activities": for_account(&fixtures.activities, id) }))
+        }
+        ["v1", "accounts", id, "balances"] => match fixtures.balances.get(*id) {
+            Some(balances) => ok(balances),
+            None => error(404, 1001, "Account not found"),
+        },
+        ["v1", "accounts", id, "positions"] => {
+            ok(json!({ "positions": for_account(&fixtures.positions, id) }))
+        }
+        ["v1", "accounts", id, "executions"] => {
+            ok(json!({ "executions": for_account(&fixtures.executions, id) }))
+        }
+        ["v1", "accounts", id, "orders"] => {
+            ok(json!({ "orders": for_account(&fixtures.orders, id) }))
+        }
+        ["v1", "accounts", id, "orders", order_id] => {
+            let order = fixtures
+                .orders
+                .get(*id)
+                .and_then(|orders| orders.iter().find(|o| o.id.to_string() == *order_id));
+            match order {
+                Some(order) => ok(order),
+                None => error(404, 1001, "Order not found"),
+            }
+        }
+        ["v1", "markets"] => ok(json!({ "markets": fixtures.markets })),
+        ["v1", "markets", "quotes"] => {
+            let ids: Vec<i64> = url
+                .query_pairs()
+                .filter(|(k, _)| k == "ids")
+                .flat_map(|(_, v)| {
+                    v.split(',')
+                        .filter_map(|id| id.parse().ok())
+                        .collect::<Vec<i64>>()
+                })
+                .collect();
+            let quotes: Vec<&Quote> = fixtures
+                .quotes
+                .iter()
+                .filter(|q| ids.contains(&q.symbol_id))
+                .collect();
+            ok(json!({ "quotes": quotes }))
+        }
+        ["v1", "markets", "quotes", id] => {
+            let quotes: Vec<&Quote> = fixtures
+                .quotes
+                .iter()
+                .filter(|q| q.symbol_id.to_string() == *id)
+                .collect();
+            ok(json!({ "quotes": quotes }))
+        }
+        ["v1", "markets", "candles", id] => {
+            let candles = id
+                .parse::<i64>()
+                .ok()
+                .and_then(|id| fixtures.candles.get(&id).cloned())
+                .unwrap_or_default();
+            ok(json!({ "candles": candles }))
+        }
+        ["v1", "symbols"] => {
+            let wanted: Vec<String> = url
+                .query_pairs()
+                .filter(|(k, _)| k == "ids" || k == "names")
+                .flat_map(|(_, v)| v.split(',').map(String::from).collect::<Vec<_>>())
+                .collect();
+            let symbols: Vec<&SymbolDetails> = fixtures
+                .symbol_details
+                .iter()
+                .filter(|s| wanted.contains(&s.symbol_id.to_string()) || wanted.contains(&s.symbol))
+                .collect();
+            ok(json!({ "symbols": symbols }))
+        }
+        ["v1", "symbols", "search"] => {
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.into_owned())
+            };
+            let prefix = param("prefix").unwrap_or_default().to_uppercase();
+            let offset = param("offset").and_then(|v| v.parse().ok()).unwrap_or(0);
+            let symbols: Vec<&EquitySymbol> = fixtures
+                .symbols
+                .iter()
+                .filter(|s| s.symbol.to_uppercase().starts_with(&prefix))
+                .skip(offset)
+                .collect();
+            ok(json!({ "symbols": symbols }))
+        }
+        ["v1", "symbols", id, "options"] => {
+            let chain = id
+                .parse::<i64>()
+                .ok()
+                .and_then(|id| fixtures.option_chains.get(&id).cloned())
+                .unwrap_or_default();
+            ok(json!({ "optionChain": chain.expiries }))
+        }
+        ["v1", "symbols", id] => {
+            let symbols: Vec<&SymbolDetails> = fixtures
+                .symbol_details
+                .iter()
+                .filter(|s| s.symbol_id.to_string() == *id)
+                .collect();
+            ok(json!({ "symbols": symbols }))
+        }
+        _ => error(404, 1001, "Invalid endpoint"),
+    }
+}
+
+fn token(state: &mut State, request: &RecordedRequest) -> TestResponse {
+    let param = |name: &str| {
+        url::form_urlencoded::parse(request.body.as_bytes())
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let accepted = match param("grant_type").as_deref() {
+        Some("refresh_token") => param("refresh_token") == Some(state.refresh_token.clone()),
+        Some("authorization_code") => param("code").is_some(),
+        _ => false,
+    };
+    if !accepted {
+        return error(400, 1017, "Refresh token is invalid");
+    }
+
+    state.issued += 1;
+    let access_token = format!("mock-access-{}", state.issued);
+    state.access_token = Some(access_token.clone());
+    state.refresh_token = format!("mock-refresh-{}", state.issued);
+    ok(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": 1800,
+        "refresh_token": state.refresh_token,
+        "api_server": state.api_server,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{errors::ApiErrorCode, retry::RetryPolicy};
+
+    fn account(number: &str) -> Account {
+        serde_json::from_value(json!({
+            "type": "Margin",
+            "number": number,
+            "status": "Active",
+            "isPrimary": true,
+            "isBilling": true,
+            "clientAccountType": "Individual"
+        }))
+        .unwrap()
+    }
+
+    fn client(mock: &MockServer) -> Client {
+        mock.client_builder()
+            .http_client(reqwest::Client::new())
+            .consumer_key(String::from("consumer"))
+            .retry_policy(RetryPolicy::default().initial_backoff(Duration::ZERO))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mock_server_serves_fixtures() {
+        let mock = MockServer::start(Fixtures {
+            accounts: vec![account("1")],
+            ..Fixtures::default()
+        })
+        .await;
+        let client = client(&mock);
+
+        let token = client.refresh_token(&mock.refresh_token()).await.unwrap();
+        assert_eq!(mock.url().as_str(), token.api_server);
+        assert_eq!(mock.refresh_token(), token.refresh_token);
+        assert_eq!(vec![account("1")], client.accounts(&token).await.unwrap());
+        assert!(client.time(&token).await.is_ok());
+        assert!(client.markets(&token).await.unwrap().is_empty());
+
+        mock.update_fixtures(|f| f.accounts.push(account("2")));
+        assert_eq!(2, client.accounts(&token).await.unwrap().len());
+
+        let err = client.account_balances(&token, "1").await.unwrap_err();
+        assert_eq!(Some(ApiErrorCode::InvalidEndpoint), err.api_error_code());
+    }
+
+    #[tokio::test]
+    async fn mock_server_rotates_and_expires_tokens() {
+        let mock = MockServer::start(Fixtures::default()).await;
+        let client = client(&mock);
+        let first = mock.refresh_token();
+        let token = client.refresh_token(&first).await.unwrap();
+
+        assert!(client.refresh_token(&first).await.is_err());
+        mock.expire_access_token();
+        let err = client.time(&token).await.unwrap_err();
+        assert!(err.is_auth_error());
+
+        let session = client.session(token);
+        assert!(session.time().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn mock_server_injects_faults() {
+        let mock = MockServer::start(Fixtures::default()).await;
+        let client = client(&mock);
+        let token = client.refresh_token(&mock.refresh_token()).await.unwrap();
+
+        mock.fail_next(Fault::ServerError);
+        mock.fail_next(Fault::RateLimited);
+        assert!(client.time(&token).await.is_ok());
+        assert_eq!(4, mock.request_count());
+
+        mock.fail_next(Fault::ExpiredToken);
+        assert!(client.time(&token).await.unwrap_err().is_auth_error());
+    }
+}
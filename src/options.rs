@@ -0,0 +1,507 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::ApiToken, errors::QuestradeError, response::Response, Client, ListingExchange,
+    OptionExerciseType, OptionType,
+};
+
+/// The call and put listed at one strike price.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StrikeContracts {
+    pub strike_price: f64,
+    pub call_symbol_id: i64,
+    pub put_symbol_id: i64,
+}
+
+/// The strikes listed for one option root. Adjusted options get a root of their own.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainRoot {
+    pub root: String,
+    pub multiplier: Option<i64>,
+    /// Sorted by strike price in chains returned by the client. The helpers below do not rely on
+    /// it.
+    pub chain_per_strike_price: Vec<StrikeContracts>,
+}
+
+impl ChainRoot {
+    /// Strikes from `lo` to `hi`, both included, by strike price.
+    pub fn strikes_between(&self, lo: f64, hi: f64) -> Vec<&StrikeContracts> {
+        let mut strikes: Vec<&StrikeContracts> = self
+            .chain_per_strike_price
+            .iter()
+            .filter(|s| s.strike_price >= lo && s.strike_price <= hi)
+            .collect();
+        strikes.sort_by(|a, b| a.strike_price.total_cmp(&b.strike_price));
+        strikes
+    }
+
+    /// The strike closest to `price`, the lower one on a tie.
+    pub fn atm_strike(&self, price: f64) -> Option<&StrikeContracts> {
+        self.chain_per_strike_price.iter().min_by(|a, b| {
+            (a.strike_price - price)
+                .abs()
+                .total_cmp(&(b.strike_price - price).abs())
+                .then(a.strike_price.total_cmp(&b.strike_price))
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionExpiry {
+    pub expiry_date: DateTime<Utc>,
+    pub description: String,
+    pub listing_exchange: ListingExchange,
+    pub option_exercise_type: OptionExerciseType,
+    pub chain_per_root: Vec<ChainRoot>,
+}
+
+impl OptionExpiry {
+    /// The standard root, which Questrade lists first.
+    pub fn primary_root(&self) -> Option<&ChainRoot> {
+        self.chain_per_root.first()
+    }
+
+    pub fn root(&self, root: &str) -> Option<&ChainRoot> {
+        self.chain_per_root.iter().find(|r| r.root == root)
+    }
+}
+
+/// The options listed on an underlying, by expiry date, then root, then strike price.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct OptionChain {
+    /// Sorted by expiry date in chains returned by the client. The helpers below do not rely on
+    /// it.
+    pub expiries: Vec<OptionExpiry>,
+}
+
+impl OptionChain {
+    /// Every expiry date, earliest first.
+    pub fn expiry_dates(&self) -> Vec<DateTime<Utc>> {
+        let mut dates: Vec<DateTime<Utc>> = self.expiries.iter().map(|e| e.expiry_date).collect();
+        dates.sort();
+        dates
+    }
+
+    pub fn expiry(&self, date: &DateTime<Utc>) -> Option<&OptionExpiry> {
+        self.expiries.iter().find(|e| e.expiry_date == *date)
+    }
+
+    /// The first expiry on or after `date`.
+    pub fn nearest_expiry_after(&self, date: &DateTime<Utc>) -> Option<&OptionExpiry> {
+        self.expiries
+            .iter()
+            .filter(|e| e.expiry_date >= *date)
+            .min_by_key(|e| e.expiry_date)
+    }
+}
+
+impl From<Vec<OptionExpiry>> for OptionChain {
+    fn from(mut expiries: Vec<OptionExpiry>) -> Self {
+        expiries.sort_by_key(|e| e.expiry_date);
+        for root in expiries
+            .iter_mut()
+            .flat_map(|e| e.chain_per_root.iter_mut())
+        {
+            root.chain_per_strike_price
+                .sort_by(|a, b| a.strike_price.total_cmp(&b.strike_price));
+        }
+        OptionChain { expiries }
+    }
+}
+
+/// An option contract as named in symbols, e.g. `AAPL17Jan25C150.00` as Questrade writes it or
+/// `AAPL  250117C00150000` in the OCC format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionSymbol {
+    /// The option root, which is the underlying's symbol unless the contract was adjusted, e.g.
+    /// after a split. Adjusted contracts add a digit, as in `BMO1`.
+    pub root: String,
+    pub expiry: NaiveDate,
+    pub option_type: OptionType,
+    pub strike: f64,
+}
+
+impl OptionSymbol {
+    pub fn new(root: &str, expiry: NaiveDate, option_type: OptionType, strike: f64) -> Self {
+        OptionSymbol {
+            root: root.to_string(),
+            expiry,
+            option_type,
+            strike,
+        }
+    }
+
+    /// The underlying's symbol, i.e. the root without the digit of an adjusted contract.
+    pub fn underlying(&self) -> &str {
+        match self.root.trim_end_matches(|c: char| c.is_ascii_digit()) {
+            "" => &self.root,
+            underlying => underlying,
+        }
+    }
+
+    /// Parses either format.
+    pub fn parse(symbol: &str) -> Result<Self, QuestradeError> {
+        Self::parse_occ(symbol).or_else(|_| Self::parse_questrade(symbol))
+    }
+
+    /// Parses `<root><ddMmmyy><C|P><strike>`, e.g. `AAPL17Jan25C150.00`.
+    pub fn parse_questrade(symbol: &str) -> Result<Self, QuestradeError> {
+        let invalid = || QuestradeError::InvalidOptionSymbol(symbol.to_string());
+        let s = symbol.trim();
+        if !s.is_ascii() {
+            return Err(invalid());
+        }
+        let type_at = s
+            .rfind(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let date_at = type_at
+            .checked_sub(7)
+            .filter(|&i| i > 0)
+            .ok_or_else(invalid)?;
+        let expiry =
+            NaiveDate::parse_from_str(&s[date_at..type_at], "%d%b%y").map_err(|_| invalid())?;
+        Ok(OptionSymbol {
+            root: s[..date_at].to_string(),
+            expiry,
+            option_type: option_type(&s[type_at..type_at + 1]).ok_or_else(invalid)?,
+            strike: strike(&s[type_at + 1..]).ok_or_else(invalid)?,
+        })
+    }
+
+    /// Parses `<root padded to 6><yymmdd><C|P><strike x 1000, 8 digits>`, e.g.
+    /// `AAPL  250117C00150000`. The padding may be left out.
+    pub fn parse_occ(symbol: &str) -> Result<Self, QuestradeError> {
+        let invalid = || QuestradeError::InvalidOptionSymbol(symbol.to_string());
+        let s = symbol.trim();
+        if !s.is_ascii() || s.len() < 16 {
+            return Err(invalid());
+        }
+        let (root, tail) = s.split_at(s.len() - 15);
+        let root = root.trim_end();
+        if root.is_empty() || root.len() > 6 || root.contains(' ') {
+            return Err(invalid());
+        }
+        let digits = |d: &str| d.bytes().all(|b| b.is_ascii_digit());
+        if !digits(&tail[..6]) || !digits(&tail[7..]) {
+            return Err(invalid());
+        }
+        let expiry = NaiveDate::parse_from_str(&tail[..6], "%y%m%d").map_err(|_| invalid())?;
+        let strike: u64 = tail[7..].parse().map_err(|_| invalid())?;
+        Ok(OptionSymbol {
+            root: root.to_string(),
+            expiry,
+            option_type: option_type(&tail[6..7]).ok_or_else(invalid)?,
+            strike: strike as f64 / 1000.0,
+        })
+    }
+
+    pub fn to_questrade(&self) -> String {
+        format!(
+            "{}{}{}{:.2}",
+            self.root,
+            self.expiry.format("%d%b%y"),
+            self.type_code(),
+            self.strike
+        )
+    }
+
+    /// The OCC symbol, with the root padded to 6 characters. Fails if the root is longer than
+    /// that or the strike does not fit in 8 digits once multiplied by 1000.
+    pub fn to_occ(&self) -> Result<String, QuestradeError> {
+        let strike = (self.strike * 1000.0).round();
+        if self.root.is_empty()
+            || self.root.len() > 6
+            || self.root.contains(' ')
+            || !(1.0..=99_999_999.0).contains(&strike)
+        {
+            return Err(QuestradeError::InvalidOptionSymbol(self.to_questrade()));
+        }
+        Ok(format!(
+            "{:<6}{}{}{:08}",
+            self.root,
+            self.expiry.format("%y%m%d"),
+            self.type_code(),
+            strike as u64
+        ))
+    }
+
+    fn type_code(&self) -> char {
+        match self.option_type {
+            OptionType::Call => 'C',
+            OptionType::Put => 'P',
+        }
+    }
+}
+
+fn option_type(code: &str) -> Option<OptionType> {
+    match code {
+        "C" | "c" => Some(OptionType::Call),
+        "P" | "p" => Some(OptionType::Put),
+        _ => None,
+    }
+}
+
+fn strike(s: &str) -> Option<f64> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|strike| strike.is_finite() && *strike > 0.0)
+}
+
+impl FromStr for OptionSymbol {
+    type Err = QuestradeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OptionSymbol::parse(s)
+    }
+}
+
+/// Formats as Questrade does.
+impl fmt::Display for OptionSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_questrade())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OptionChainData {
+    pub option_chain: Vec<OptionExpiry>,
+}
+
+impl Client {
+    pub async fn symbol_options(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<OptionChain, QuestradeError> {
+        self.symbol_options_with_meta(token, symbol_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn symbol_options_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<OptionChain>, QuestradeError> {
+        let response: Response<OptionChainData> = self
+            .send_with_meta(self.base_request(
+                Method::GET,
+                token,
+                &format!("v1/symbols/{}/options", symbol_id),
+            )?)
+            .await?;
+        Ok(response.map(|data| OptionChain::from(data.option_chain)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn chain() -> OptionChain {
+        let data = r#"
+        {
+            "optionChain": [
+                {
+                    "expiryDate": "2015-02-20T00:00:00.000000-05:00",
+                    "description": "BANK OF MONTREAL",
+                    "listingExchange": "MX",
+                    "optionExerciseType": "American",
+                    "chainPerRoot": [
+                        {
+                            "root": "BMO",
+                            "chainPerStrikePrice": [
+                                { "strikePrice": 70, "callSymbolId": 6102020, "putSymbolId": 6102021 }
+                            ],
+                            "multiplier": 100
+                        }
+                    ]
+                },
+                {
+                    "expiryDate": "2015-01-17T00:00:00.000000-05:00",
+                    "description": "BANK OF MONTREAL",
+                    "listingExchange": "MX",
+                    "optionExerciseType": "American",
+                    "chainPerRoot": [
+                        {
+                            "root": "BMO",
+                            "chainPerStrikePrice": [
+                                { "strikePrice": 65, "callSymbolId": 6101994, "putSymbolId": 6102010 },
+                                { "strikePrice": 60, "callSymbolId": 6101993, "putSymbolId": 6102009 },
+                                { "strikePrice": 70, "callSymbolId": 6101995, "putSymbolId": 6102011 }
+                            ],
+                            "multiplier": 100
+                        },
+                        {
+                            "root": "BMO1",
+                            "chainPerStrikePrice": [],
+                            "multiplier": 50
+                        }
+                    ]
+                }
+            ]
+        }
+        "#;
+        let d: OptionChainData = serde_json::from_str(data).expect("failed to deserialize JSON");
+        OptionChain::from(d.option_chain)
+    }
+
+    #[test]
+    fn option_chain_is_sorted() {
+        let chain = chain();
+        assert_eq!(
+            vec![date("2015-01-17T05:00:00Z"), date("2015-02-20T05:00:00Z")],
+            chain.expiry_dates()
+        );
+        let strikes: Vec<f64> = chain.expiries[0].chain_per_root[0]
+            .chain_per_strike_price
+            .iter()
+            .map(|s| s.strike_price)
+            .collect();
+        assert_eq!(vec![60.0, 65.0, 70.0], strikes);
+        assert_eq!(Some(50), chain.expiries[0].root("BMO1").unwrap().multiplier);
+    }
+
+    #[test]
+    fn option_chain_navigation_works() {
+        let chain = chain();
+        let expiry = chain
+            .nearest_expiry_after(&date("2015-01-01T00:00:00Z"))
+            .unwrap();
+        assert_eq!(date("2015-01-17T05:00:00Z"), expiry.expiry_date);
+        assert_eq!(
+            date("2015-02-20T05:00:00Z"),
+            chain
+                .nearest_expiry_after(&date("2015-01-18T00:00:00Z"))
+                .unwrap()
+                .expiry_date
+        );
+        assert!(chain
+            .nearest_expiry_after(&date("2015-03-01T00:00:00Z"))
+            .is_none());
+        assert!(chain.expiry(&date("2015-01-17T05:00:00Z")).is_some());
+
+        let root = expiry.primary_root().unwrap();
+        assert_eq!("BMO", root.root);
+        let ids: Vec<i64> = root
+            .strikes_between(61.0, 70.0)
+            .iter()
+            .map(|s| s.call_symbol_id)
+            .collect();
+        assert_eq!(vec![6101994, 6101995], ids);
+        assert_eq!(65.0, root.atm_strike(66.1).unwrap().strike_price);
+        assert_eq!(60.0, root.atm_strike(62.5).unwrap().strike_price);
+        assert!(expiry.root("BMO1").unwrap().atm_strike(60.0).is_none());
+    }
+
+    #[test]
+    fn option_chain_helpers_do_not_assume_order() {
+        let mut chain = chain();
+        chain.expiries.reverse();
+        for expiry in &mut chain.expiries {
+            for root in &mut expiry.chain_per_root {
+                root.chain_per_strike_price.reverse();
+            }
+        }
+
+        assert_eq!(
+            vec![date("2015-01-17T05:00:00Z"), date("2015-02-20T05:00:00Z")],
+            chain.expiry_dates()
+        );
+        let expiry = chain
+            .nearest_expiry_after(&date("2015-01-01T00:00:00Z"))
+            .unwrap();
+        assert_eq!(date("2015-01-17T05:00:00Z"), expiry.expiry_date);
+
+        let root = expiry.primary_root().unwrap();
+        let ids: Vec<i64> = root
+            .strikes_between(61.0, 70.0)
+            .iter()
+            .map(|s| s.call_symbol_id)
+            .collect();
+        assert_eq!(vec![6101994, 6101995], ids);
+        assert_eq!(60.0, root.atm_strike(62.5).unwrap().strike_price);
+    }
+
+    #[test]
+    fn option_symbol_parsing_works() {
+        let expected = OptionSymbol::new(
+            "AAPL",
+            NaiveDate::from_ymd_opt(2025, 1, 17).unwrap(),
+            OptionType::Call,
+            150.0,
+        );
+        assert_eq!(expected, "AAPL17Jan25C150.00".parse().unwrap());
+        assert_eq!(expected, "AAPL  250117C00150000".parse().unwrap());
+        assert_eq!(expected, "AAPL250117C00150000".parse().unwrap());
+
+        let adjusted = OptionSymbol::parse("BMO120Jan17P72.5").unwrap();
+        assert_eq!("BMO1", adjusted.root);
+        assert_eq!("BMO", adjusted.underlying());
+        assert_eq!(OptionType::Put, adjusted.option_type);
+        assert_eq!(72.5, adjusted.strike);
+
+        for invalid in [
+            "AAPL",
+            "17Jan25C150.00",
+            "AAPL17Foo25C150.00",
+            "AAPL17Jan25X150.00",
+            "AAPL17Jan25C",
+            "AAPL  251317C00150000",
+            "TOOLONG250117C00150000",
+        ] {
+            assert!(
+                matches!(
+                    OptionSymbol::parse(invalid),
+                    Err(QuestradeError::InvalidOptionSymbol(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn option_symbol_formatting_works() {
+        let symbol = OptionSymbol::parse("SPY   240621P00512500").unwrap();
+        assert_eq!("SPY21Jun24P512.50", symbol.to_questrade());
+        assert_eq!("SPY21Jun24P512.50", symbol.to_string());
+        assert_eq!("SPY   240621P00512500", symbol.to_occ().unwrap());
+
+        let symbol = OptionSymbol::parse("AAPL17Jan25C150.00").unwrap();
+        assert_eq!("AAPL  250117C00150000", symbol.to_occ().unwrap());
+        assert_eq!(
+            symbol,
+            OptionSymbol::parse(&symbol.to_occ().unwrap()).unwrap()
+        );
+        assert_eq!("AAPL", symbol.underlying());
+    }
+
+    #[test]
+    fn option_symbol_rejects_what_occ_cannot_hold() {
+        let expiry = NaiveDate::from_ymd_opt(2025, 1, 17).unwrap();
+        let long_root = OptionSymbol::new("GOOGL12", expiry, OptionType::Call, 150.0);
+        assert_eq!("GOOGL1217Jan25C150.00", long_root.to_questrade());
+        assert!(matches!(
+            long_root.to_occ(),
+            Err(QuestradeError::InvalidOptionSymbol(_))
+        ));
+
+        let max = OptionSymbol::new("BRK", expiry, OptionType::Put, 99_999.999);
+        assert_eq!("BRK   250117P99999999", max.to_occ().unwrap());
+        let huge = OptionSymbol::new("BRK", expiry, OptionType::Put, 100_000.0);
+        assert!(huge.to_occ().is_err());
+    }
+}
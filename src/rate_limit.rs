@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use url::Url;
+
+const REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+const RESET_HEADER: &str = "X-RateLimit-Reset";
+
+/// Questrade limits account calls and market data calls separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitCategory {
+    Account,
+    MarketData,
+}
+
+impl RateLimitCategory {
+    pub(crate) fn from_url(url: &Url) -> Self {
+        let path = url.path();
+        if path.starts_with("/v1/markets") || path.starts_with("/v1/symbols") {
+            RateLimitCategory::MarketData
+        } else {
+            RateLimitCategory::Account
+        }
+    }
+
+    fn per_second(&self) -> usize {
+        match self {
+            RateLimitCategory::Account => 30,
+            RateLimitCategory::MarketData => 20,
+        }
+    }
+}
+
+/// The hourly budget last reported by the API for a category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub remaining: u32,
+    pub reset: DateTime<Utc>,
+}
+
+impl RateLimit {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<i64>().ok();
+        Some(RateLimit {
+            remaining: u32::try_from(header(REMAINING_HEADER)?).ok()?,
+            reset: Utc.timestamp_opt(header(RESET_HEADER)?, 0).single()?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    per_second: usize,
+    limit: Option<RateLimit>,
+    sent: VecDeque<Instant>,
+}
+
+impl Bucket {
+    fn new(per_second: usize) -> Self {
+        Bucket {
+            per_second,
+            limit: None,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Claims a slot for a request, or returns how long to wait before trying again.
+    fn try_acquire(&mut self) -> Result<(), Duration> {
+        if let Some(limit) = &mut self.limit {
+            if limit.remaining == 0 {
+                match (limit.reset - Utc::now()).to_std() {
+                    Ok(wait) if !wait.is_zero() => return Err(wait),
+                    _ => self.limit = None,
+                }
+            }
+        }
+
+        let now = Instant::now();
+        while let Some(sent) = self.sent.front() {
+            if now.duration_since(*sent) < Duration::from_secs(1) {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.per_second {
+            return Err(Duration::from_secs(1) - now.duration_since(self.sent[0]));
+        }
+
+        self.sent.push_back(now);
+        if let Some(limit) = &mut self.limit {
+            limit.remaining -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// Paces requests so they stay within Questrade's per-second limits and the hourly budget
+/// reported in the `X-RateLimit-*` response headers, delaying requests rather than letting the
+/// API reject them.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    account: Mutex<Bucket>,
+    market_data: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter {
+            account: Mutex::new(Bucket::new(RateLimitCategory::Account.per_second())),
+            market_data: Mutex::new(Bucket::new(RateLimitCategory::MarketData.per_second())),
+        }
+    }
+
+    fn bucket(&self, category: RateLimitCategory) -> &Mutex<Bucket> {
+        match category {
+            RateLimitCategory::Account => &self.account,
+            RateLimitCategory::MarketData => &self.market_data,
+        }
+    }
+
+    /// Waits until a request in `category` can be sent.
+    pub async fn acquire(&self, category: RateLimitCategory) {
+        loop {
+            let wait = match self.bucket(category).lock().unwrap().try_acquire() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tracing::debug!("rate limited, waiting {:?} for {:?} budget", wait, category);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Blocks the current thread until a request in `category` can be sent.
+    #[cfg(feature = "blocking")]
+    pub fn acquire_blocking(&self, category: RateLimitCategory) {
+        loop {
+            let wait = match self.bucket(category).lock().unwrap().try_acquire() {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tracing::debug!("rate limited, waiting {:?} for {:?} budget", wait, category);
+            std::thread::sleep(wait);
+        }
+    }
+
+    pub fn update(&self, category: RateLimitCategory, headers: &HeaderMap) {
+        if let Some(limit) = RateLimit::from_headers(headers) {
+            self.bucket(category).lock().unwrap().limit = Some(limit);
+        }
+    }
+
+    pub fn current(&self, category: RateLimitCategory) -> Option<RateLimit> {
+        self.bucket(category).lock().unwrap().limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(remaining: u32, reset: DateTime<Utc>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(REMAINING_HEADER, remaining.into());
+        headers.insert(RESET_HEADER, reset.timestamp().into());
+        headers
+    }
+
+    #[test]
+    fn category_from_url_works() {
+        let category = |url| RateLimitCategory::from_url(&Url::parse(url).unwrap());
+        assert_eq!(
+            RateLimitCategory::MarketData,
+            category("https://api01.iq.questrade.com/v1/markets/quotes?ids=1")
+        );
+        assert_eq!(
+            RateLimitCategory::MarketData,
+            category("https://api01.iq.questrade.com/v1/symbols/search")
+        );
+        assert_eq!(
+            RateLimitCategory::Account,
+            category("https://api01.iq.questrade.com/v1/accounts/1/orders")
+        );
+        assert_eq!(
+            RateLimitCategory::Account,
+            category("https://api01.iq.questrade.com/v1/time")
+        );
+    }
+
+    #[test]
+    fn update_tracks_buckets_separately() {
+        let limiter = RateLimiter::new();
+        let reset = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        limiter.update(RateLimitCategory::MarketData, &headers(42, reset));
+        limiter.update(RateLimitCategory::Account, &HeaderMap::new());
+
+        assert_eq!(
+            Some(RateLimit {
+                remaining: 42,
+                reset
+            }),
+            limiter.current(RateLimitCategory::MarketData)
+        );
+        assert_eq!(None, limiter.current(RateLimitCategory::Account));
+    }
+
+    #[test]
+    fn bucket_paces_requests_per_second() {
+        let mut bucket = Bucket::new(2);
+        assert!(bucket.try_acquire().is_ok());
+        assert!(bucket.try_acquire().is_ok());
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn bucket_waits_for_exhausted_budget_to_reset() {
+        let mut bucket = Bucket::new(10);
+        bucket.limit = Some(RateLimit {
+            remaining: 1,
+            reset: Utc::now() + chrono::Duration::seconds(60),
+        });
+        assert!(bucket.try_acquire().is_ok());
+        let wait = bucket.try_acquire().unwrap_err();
+        assert!(wait > Duration::from_secs(50));
+
+        bucket.limit = Some(RateLimit {
+            remaining: 0,
+            reset: Utc::now() - chrono::Duration::seconds(1),
+        });
+        assert!(bucket.try_acquire().is_ok());
+        assert_eq!(None, bucket.limit);
+    }
+
+    #[tokio::test]
+    async fn acquire_delays_until_slot_frees() {
+        let limiter = RateLimiter::new();
+        *limiter.account.lock().unwrap() = Bucket::new(1);
+        let start = Instant::now();
+        limiter.acquire(RateLimitCategory::Account).await;
+        limiter.acquire(RateLimitCategory::Account).await;
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+}
@@ -0,0 +1,608 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::ApiToken,
+    errors::{ApiErrorCode, QuestradeError},
+    session::Session,
+    store::{read_optional, write_atomic},
+    symbols::{EquitySymbol, SearchPages, SymbolDetails},
+    Client, Currency, ListingExchange, SecurityType,
+};
+
+/// A ticker to resolve, optionally narrowed down to one listing, e.g. the TSX listing of SHOP
+/// rather than the NYSE one.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct SymbolQuery {
+    pub ticker: String,
+    pub exchange: Option<ListingExchange>,
+    pub currency: Option<Currency>,
+}
+
+impl SymbolQuery {
+    pub fn new(ticker: &str) -> Self {
+        SymbolQuery {
+            ticker: ticker.trim().to_uppercase(),
+            exchange: None,
+            currency: None,
+        }
+    }
+
+    pub fn exchange(mut self, exchange: ListingExchange) -> Self {
+        self.exchange = Some(exchange);
+        self
+    }
+
+    pub fn currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// Names the symbol may be listed under, in order of preference. Canadian listings carry
+    /// an exchange suffix, e.g. `SHOP.TO`.
+    fn candidates(&self) -> Vec<String> {
+        if self.ticker.contains('.') {
+            return vec![self.ticker.clone()];
+        }
+        let suffixes: &[&str] = match &self.exchange {
+            Some(ListingExchange::TSX) => &[".TO"],
+            Some(ListingExchange::TSXV) => &[".VN"],
+            Some(ListingExchange::CNSX) => &[".CN"],
+            Some(_) => &[""],
+            None => &["", ".TO", ".VN", ".CN"],
+        };
+        suffixes
+            .iter()
+            .map(|suffix| format!("{}{}", self.ticker, suffix))
+            .collect()
+    }
+
+    fn matches(&self, symbol: &ResolvedSymbol) -> bool {
+        self.exchange.iter().all(|e| *e == symbol.listing_exchange)
+            && self.currency.iter().all(|c| *c == symbol.currency)
+    }
+}
+
+impl From<&str> for SymbolQuery {
+    fn from(ticker: &str) -> Self {
+        SymbolQuery::new(ticker)
+    }
+}
+
+impl fmt::Display for SymbolQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ticker)?;
+        if let Some(exchange) = &self.exchange {
+            write!(f, " on {}", exchange)?;
+        }
+        if let Some(currency) = &self.currency {
+            write!(f, " in {}", currency)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedSymbol {
+    pub symbol: String,
+    pub symbol_id: i64,
+    pub listing_exchange: ListingExchange,
+    pub currency: Currency,
+    pub security_type: SecurityType,
+}
+
+impl From<&EquitySymbol> for ResolvedSymbol {
+    fn from(symbol: &EquitySymbol) -> Self {
+        ResolvedSymbol {
+            symbol: symbol.symbol.clone(),
+            symbol_id: symbol.symbol_id,
+            listing_exchange: symbol.listing_exchange.clone(),
+            currency: symbol.currency.clone(),
+            security_type: symbol.security_type.clone(),
+        }
+    }
+}
+
+impl From<&SymbolDetails> for ResolvedSymbol {
+    fn from(details: &SymbolDetails) -> Self {
+        ResolvedSymbol {
+            symbol: details.symbol.clone(),
+            symbol_id: details.symbol_id,
+            listing_exchange: details.listing_exchange.clone(),
+            currency: details.currency.clone(),
+            security_type: details.security_type.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    query: SymbolQuery,
+    symbol: ResolvedSymbol,
+    resolved_at: DateTime<Utc>,
+}
+
+/// Where a [`SymbolResolver`] looks symbols up.
+pub(crate) enum Lookup<'a> {
+    Client(&'a Client, &'a ApiToken),
+    Session(&'a Session),
+}
+
+impl Lookup<'_> {
+    /// Details of each of `names` that exists. A name Questrade doesn't know fails the whole
+    /// request with [`ApiErrorCode::NotFound`], which is taken as no matches.
+    async fn by_names(&self, names: Vec<String>) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        let found = match self {
+            Lookup::Client(client, token) => client.symbols_by_names(token, names).await,
+            Lookup::Session(session) => session.symbols_by_names(names).await,
+        };
+        match found {
+            Err(err) if err.api_error_code() == Some(ApiErrorCode::NotFound) => Ok(Vec::new()),
+            found => found,
+        }
+    }
+
+    /// Searches for `name`, paging until a symbol of exactly that name shows up or the matches
+    /// run out.
+    async fn find(&self, name: &str) -> Result<Option<EquitySymbol>, QuestradeError> {
+        let mut pages = SearchPages::default();
+        while let Some(offset) = pages.next_offset() {
+            let page = match self {
+                Lookup::Client(client, token) => client.symbol_search(token, name, offset).await?,
+                Lookup::Session(session) => session.symbol_search(name, offset).await?,
+            };
+            if let Some(symbol) = page.iter().find(|s| s.symbol.eq_ignore_ascii_case(name)) {
+                return Ok(Some(symbol.clone()));
+            }
+            pages.add(page);
+        }
+        Ok(None)
+    }
+}
+
+/// Resolves tickers to the `symbol_id` the market endpoints take, caching the results. The names
+/// each uncached ticker may be listed as are looked up together, with as few requests as the
+/// query length limit allows, and tickers not found that way with the symbol search.
+///
+/// Entries are kept in memory, and in a JSON file if one is given. They are looked up again
+/// once older than the TTL, if one is set.
+#[derive(Debug, Default)]
+pub struct SymbolResolver {
+    cache: Mutex<HashMap<SymbolQuery, CacheEntry>>,
+    path: Option<PathBuf>,
+    ttl: Option<Duration>,
+}
+
+impl SymbolResolver {
+    pub fn new() -> Self {
+        SymbolResolver::default()
+    }
+
+    /// Persists the cache to `path`, loading the entries already stored there.
+    pub fn with_file<P: AsRef<Path>>(path: P) -> Result<Self, QuestradeError> {
+        let path = path.as_ref().to_path_buf();
+        let entries: Vec<CacheEntry> = match read_optional(&path)? {
+            Some(contents) => serde_json::from_slice(&contents).map_err(|err| {
+                QuestradeError::StoreError(format!("failed to parse {}: {}", path.display(), err))
+            })?,
+            None => Vec::new(),
+        };
+        Ok(SymbolResolver {
+            cache: Mutex::new(entries.into_iter().map(|e| (e.query.clone(), e)).collect()),
+            path: Some(path),
+            ttl: None,
+        })
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn clear(&self) -> Result<(), QuestradeError> {
+        self.cache.lock().unwrap().clear();
+        self.save()
+    }
+
+    pub async fn resolve<Q>(
+        &self,
+        client: &Client,
+        token: &ApiToken,
+        query: Q,
+    ) -> Result<ResolvedSymbol, QuestradeError>
+    where
+        Q: Into<SymbolQuery>,
+    {
+        let lookup = Lookup::Client(client, token);
+        self.resolve_with(&lookup, vec![query.into()])
+            .await?
+            .remove(0)
+    }
+
+    /// Resolves every query, each either to its symbol or to
+    /// [`QuestradeError::UnresolvedSymbol`]. The outer error is for failed requests and failures
+    /// to save the cache.
+    pub async fn resolve_all(
+        &self,
+        client: &Client,
+        token: &ApiToken,
+        queries: Vec<SymbolQuery>,
+    ) -> Result<Vec<Result<ResolvedSymbol, QuestradeError>>, QuestradeError> {
+        self.resolve_with(&Lookup::Client(client, token), queries)
+            .await
+    }
+
+    pub(crate) async fn resolve_with(
+        &self,
+        lookup: &Lookup<'_>,
+        queries: Vec<SymbolQuery>,
+    ) -> Result<Vec<Result<ResolvedSymbol, QuestradeError>>, QuestradeError> {
+        let cached: Vec<Option<ResolvedSymbol>> = queries.iter().map(|q| self.cached(q)).collect();
+        let mut names: Vec<String> = queries
+            .iter()
+            .zip(&cached)
+            .filter(|(_, cached)| cached.is_none())
+            .flat_map(|(query, _)| query.candidates())
+            .collect();
+        names.sort();
+        names.dedup();
+        let details = if names.is_empty() {
+            Vec::new()
+        } else {
+            lookup.by_names(names).await?
+        };
+
+        // Searches already made for this batch, by symbol name.
+        let mut searched: HashMap<String, Option<ResolvedSymbol>> = HashMap::new();
+        let mut inserted = false;
+        let mut results = Vec::with_capacity(queries.len());
+        for (query, cached) in queries.into_iter().zip(cached) {
+            if let Some(symbol) = cached {
+                results.push(Ok(symbol));
+                continue;
+            }
+            let found = match Self::listed(&query, &details) {
+                Some(symbol) => Some(symbol),
+                None => Self::search(lookup, &query, &mut searched).await?,
+            };
+            match found {
+                Some(symbol) => {
+                    self.insert(&query, &symbol);
+                    inserted = true;
+                    results.push(Ok(symbol));
+                }
+                None => results.push(Err(QuestradeError::UnresolvedSymbol(query.to_string()))),
+            }
+        }
+        if inserted {
+            self.save()?;
+        }
+        Ok(results)
+    }
+
+    /// The listing among `details` of the first candidate name that exists and matches `query`.
+    fn listed(query: &SymbolQuery, details: &[SymbolDetails]) -> Option<ResolvedSymbol> {
+        query.candidates().into_iter().find_map(|name| {
+            details
+                .iter()
+                .filter(|d| d.symbol.eq_ignore_ascii_case(&name))
+                .map(ResolvedSymbol::from)
+                .find(|symbol| query.matches(symbol))
+        })
+    }
+
+    /// Like [`SymbolResolver::listed`], looking each candidate name up with the symbol search.
+    async fn search(
+        lookup: &Lookup<'_>,
+        query: &SymbolQuery,
+        searched: &mut HashMap<String, Option<ResolvedSymbol>>,
+    ) -> Result<Option<ResolvedSymbol>, QuestradeError> {
+        for name in query.candidates() {
+            if !searched.contains_key(&name) {
+                let found = lookup.find(&name).await?;
+                searched.insert(name.clone(), found.as_ref().map(ResolvedSymbol::from));
+            }
+            if let Some(symbol) = searched[&name].as_ref().filter(|s| query.matches(s)) {
+                return Ok(Some(symbol.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn cached(&self, query: &SymbolQuery) -> Option<ResolvedSymbol> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(query)
+            .filter(|entry| self.is_fresh(entry))
+            .map(|entry| entry.symbol.clone())
+    }
+
+    fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        match self.ttl {
+            Some(ttl) => (Utc::now() - entry.resolved_at)
+                .to_std()
+                .map_or(true, |age| age < ttl),
+            None => true,
+        }
+    }
+
+    fn insert(&self, query: &SymbolQuery, symbol: &ResolvedSymbol) {
+        let entry = CacheEntry {
+            query: query.clone(),
+            symbol: symbol.clone(),
+            resolved_at: Utc::now(),
+        };
+        self.cache.lock().unwrap().insert(query.clone(), entry);
+    }
+
+    fn save(&self) -> Result<(), QuestradeError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let entries: Vec<CacheEntry> = self.cache.lock().unwrap().values().cloned().collect();
+        let contents = serde_json::to_vec_pretty(&entries)
+            .map_err(|err| QuestradeError::StoreError(err.to_string()))?;
+        write_atomic(path, &contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_server::{test_client, FakeTokens, TestResponse, TestServer};
+
+    fn listing(symbol: &str, symbol_id: i64, exchange: &str, currency: &str) -> String {
+        format!(
+            r#"{{"symbol": "{}", "symbolId": {}, "description": "SHOPIFY INC", "securityType": "Stock", "listingExchange": "{}", "currency": "{}", "isTradable": true, "isQuotable": true, "hasOptions": true}}"#,
+            symbol, symbol_id, exchange, currency
+        )
+    }
+
+    /// Serves symbols by name, leaving out RY.TO, and the symbol search one match per page,
+    /// with SHOPX ahead of SHOP. Unless `by_names` is set, looking symbols up by name fails as
+    /// if one of the names did not exist.
+    async fn server(by_names: bool) -> TestServer {
+        let listings = [
+            ("SHOPX", listing("SHOPX", 1003, "NASDAQ", "USD")),
+            ("SHOP", listing("SHOP", 1001, "NYSE", "USD")),
+            ("SHOP.TO", listing("SHOP.TO", 1002, "TSX", "CAD")),
+            ("RY.TO", listing("RY.TO", 1004, "TSX", "CAD")),
+        ];
+        TestServer::start(move |request| {
+            let url = url::Url::parse("http://localhost")
+                .unwrap()
+                .join(&request.target)
+                .unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(k, _)| k == name)
+                    .map(|(_, v)| v.into_owned())
+                    .unwrap_or_default()
+            };
+            if url.path() == "/v1/symbols" {
+                if !by_names {
+                    return TestResponse::json(404, r#"{"code": 1019, "message": "Not found"}"#);
+                }
+                let names = param("names");
+                let found: Vec<&str> = listings
+                    .iter()
+                    .filter(|(symbol, _)| {
+                        *symbol != "RY.TO" && names.split(',').any(|n| n == *symbol)
+                    })
+                    .map(|(_, listing)| listing.as_str())
+                    .collect();
+                return TestResponse::json(
+                    200,
+                    &format!(r#"{{"symbols": [{}]}}"#, found.join(",")),
+                );
+            }
+            let prefix = param("prefix");
+            let offset: usize = param("offset").parse().unwrap();
+            let page: Vec<&str> = listings
+                .iter()
+                .filter(|(symbol, _)| symbol.starts_with(&prefix))
+                .skip(offset)
+                .take(1)
+                .map(|(_, listing)| listing.as_str())
+                .collect();
+            TestResponse::json(200, &format!(r#"{{"symbols": [{}]}}"#, page.join(",")))
+        })
+        .await
+    }
+
+    #[test]
+    fn symbol_query_candidates_work() {
+        assert_eq!(
+            vec!["SHOP", "SHOP.TO", "SHOP.VN", "SHOP.CN"],
+            SymbolQuery::new(" shop ").candidates()
+        );
+        assert_eq!(
+            vec!["SHOP.TO"],
+            SymbolQuery::new("SHOP")
+                .exchange(ListingExchange::TSX)
+                .candidates()
+        );
+        assert_eq!(vec!["BRK.B"], SymbolQuery::new("BRK.B").candidates());
+        assert_eq!(
+            "SHOP on TSX in CAD",
+            SymbolQuery::new("SHOP")
+                .exchange(ListingExchange::TSX)
+                .currency(Currency::CAD)
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn resolver_disambiguates_and_caches() {
+        let server = server(true).await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+        let resolver = SymbolResolver::new();
+
+        let resolved = resolver
+            .resolve_all(
+                &client,
+                &token,
+                vec![
+                    SymbolQuery::new("SHOP"),
+                    SymbolQuery::new("SHOP").currency(Currency::CAD),
+                    SymbolQuery::new("SHOP").exchange(ListingExchange::TSX),
+                ],
+            )
+            .await
+            .unwrap();
+        let ids: Vec<i64> = resolved.into_iter().map(|s| s.unwrap().symbol_id).collect();
+        assert_eq!(vec![1001, 1002, 1002], ids);
+        // Every candidate name is looked up with a single request.
+        let targets: Vec<String> = server.requests().into_iter().map(|r| r.target).collect();
+        assert_eq!(
+            vec!["/v1/symbols?names=SHOP%2CSHOP.CN%2CSHOP.TO%2CSHOP.VN"],
+            targets
+        );
+
+        let shop = resolver.resolve(&client, &token, "shop").await.unwrap();
+        assert_eq!(1001, shop.symbol_id);
+        assert_eq!(1, server.requests().len());
+
+        let err = resolver
+            .resolve(
+                &client,
+                &token,
+                SymbolQuery::new("SHOP").exchange(ListingExchange::NASDAQ),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, QuestradeError::UnresolvedSymbol(q) if q == "SHOP on NASDAQ"));
+    }
+
+    #[tokio::test]
+    async fn resolve_all_reports_each_query() {
+        let server = server(true).await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+        let resolver = SymbolResolver::new();
+
+        let resolved = resolver
+            .resolve_all(
+                &client,
+                &token,
+                vec![SymbolQuery::new("NOPE"), SymbolQuery::new("SHOP")],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(&resolved[0], Err(QuestradeError::UnresolvedSymbol(q)) if q == "NOPE"));
+        assert_eq!(1001, resolved[1].as_ref().unwrap().symbol_id);
+
+        // Only the resolved query was cached.
+        let requests = server.requests().len();
+        resolver.resolve(&client, &token, "SHOP").await.unwrap();
+        assert_eq!(requests, server.requests().len());
+        assert!(resolver.resolve(&client, &token, "NOPE").await.is_err());
+        assert!(server.requests().len() > requests);
+    }
+
+    #[tokio::test]
+    async fn resolver_searches_for_symbols_not_found_by_name() {
+        let client = test_client();
+        let query = SymbolQuery::new("RY").exchange(ListingExchange::TSX);
+
+        let listed = server(true).await;
+        let token = FakeTokens::new(&listed.url).token("a1");
+        let resolved = SymbolResolver::new()
+            .resolve_all(
+                &client,
+                &token,
+                vec![query.clone(), SymbolQuery::new("SHOP")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(1004, resolved[0].as_ref().unwrap().symbol_id);
+        assert_eq!(1001, resolved[1].as_ref().unwrap().symbol_id);
+        let targets: Vec<String> = listed.requests().into_iter().map(|r| r.target).collect();
+        assert_eq!(
+            vec![
+                "/v1/symbols?names=RY.TO%2CSHOP%2CSHOP.CN%2CSHOP.TO%2CSHOP.VN",
+                "/v1/symbols/search?prefix=RY.TO&offset=0",
+            ],
+            targets
+        );
+
+        let unlisted = server(false).await;
+        let token = FakeTokens::new(&unlisted.url).token("a1");
+        let shop = SymbolResolver::new()
+            .resolve(
+                &client,
+                &token,
+                SymbolQuery::new("SHOP").currency(Currency::CAD),
+            )
+            .await
+            .unwrap();
+        assert_eq!(1002, shop.symbol_id);
+    }
+
+    #[tokio::test]
+    async fn session_resolves_symbols() {
+        let server = server(true).await;
+        let session = Session::new(test_client(), Arc::new(FakeTokens::new(&server.url)));
+        let resolver = SymbolResolver::new();
+
+        let shop = session
+            .resolve_symbol(&resolver, SymbolQuery::new("SHOP").currency(Currency::CAD))
+            .await
+            .unwrap();
+        assert_eq!(1002, shop.symbol_id);
+        let resolved = session
+            .resolve_symbols(&resolver, vec![SymbolQuery::new("SHOP")])
+            .await
+            .unwrap();
+        assert_eq!(1001, resolved[0].as_ref().unwrap().symbol_id);
+    }
+
+    #[tokio::test]
+    async fn resolver_persists_and_expires_entries() {
+        let server = server(true).await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("symbols.json");
+
+        let resolver = SymbolResolver::with_file(&path).unwrap();
+        resolver.resolve(&client, &token, "SHOP").await.unwrap();
+        assert_eq!(1, server.requests().len());
+
+        let resolver = SymbolResolver::with_file(&path).unwrap();
+        assert_eq!(
+            1001,
+            resolver
+                .resolve(&client, &token, "SHOP")
+                .await
+                .unwrap()
+                .symbol_id
+        );
+        assert_eq!(1, server.requests().len());
+
+        let resolver = SymbolResolver::with_file(&path)
+            .unwrap()
+            .ttl(Duration::ZERO);
+        resolver.resolve(&client, &token, "SHOP").await.unwrap();
+        assert_eq!(2, server.requests().len());
+    }
+}
@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::de::DeserializeOwned;
+use url::Url;
+
+use crate::{
+    cassette::redact_json,
+    errors::{ApiError, QuestradeError},
+    rate_limit::RateLimit,
+};
+
+/// How much of a response body is kept in errors.
+const BODY_SNIPPET_LEN: usize = 512;
+
+/// Data returned by an endpoint together with details of the HTTP exchange that produced it.
+#[derive(Debug, Clone)]
+pub struct Response<T> {
+    pub data: T,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The URL the response came from, after any redirects.
+    pub url: Url,
+    /// Time from sending the request until its body was read, for the final attempt only.
+    pub elapsed: Duration,
+    /// The budget reported in the `X-RateLimit-*` headers, if present.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl<T> Response<T> {
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    pub fn map<U, F>(self, f: F) -> Response<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Response {
+            data: f(self.data),
+            status: self.status,
+            headers: self.headers,
+            url: self.url,
+            elapsed: self.elapsed,
+            rate_limit: self.rate_limit,
+        }
+    }
+}
+
+/// Decodes a response like [`parse`], keeping the details of the exchange alongside the data.
+pub(crate) fn decode<T>(
+    status: StatusCode,
+    headers: &HeaderMap,
+    url: Url,
+    body: &[u8],
+    elapsed: Duration,
+) -> Result<Response<T>, QuestradeError>
+where
+    T: DeserializeOwned,
+{
+    let data = parse(status, headers, &url, body)?;
+    Ok(Response {
+        data,
+        status,
+        headers: headers.clone(),
+        url,
+        elapsed,
+        rate_limit: RateLimit::from_headers(headers),
+    })
+}
+
+/// Decodes a response body as `T`, or as the error Questrade sent instead. Error statuses are
+/// never decoded as `T`, and bodies that are not JSON, such as gateway error pages, are reported
+/// without attempting to decode them.
+pub(crate) fn parse<T>(
+    status: StatusCode,
+    headers: &HeaderMap,
+    url: &Url,
+    body: &[u8],
+) -> Result<T, QuestradeError>
+where
+    T: DeserializeOwned,
+{
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let is_json = content_type
+        .map(|value| value.contains("json"))
+        .unwrap_or(true);
+
+    if !status.is_success() {
+        if is_json {
+            if let Ok(err) = serde_json::from_slice::<ApiError>(body) {
+                return Err(QuestradeError::ApiError(err));
+            }
+        }
+        return Err(QuestradeError::HttpError {
+            status,
+            endpoint: url.path().to_string(),
+            body: snippet(body),
+        });
+    }
+
+    // Endpoints that answer 204 or an empty 200 decode as `()` or `None`.
+    let body = if body.iter().all(u8::is_ascii_whitespace) {
+        &b"null"[..]
+    } else if is_json {
+        body
+    } else {
+        return Err(QuestradeError::UnexpectedContentType {
+            endpoint: url.path().to_string(),
+            content_type: content_type.unwrap_or_default().to_string(),
+            body: snippet(body),
+        });
+    };
+    let de = &mut serde_json::Deserializer::from_slice(body);
+    let (path, source) = match serde_path_to_error::deserialize::<_, T>(&mut *de) {
+        Ok(data) => match de.end() {
+            Ok(()) => return Ok(data),
+            Err(err) => (String::from("."), err),
+        },
+        Err(err) => (err.path().to_string(), err.into_inner()),
+    };
+    Err(QuestradeError::DecodeError {
+        endpoint: url.path().to_string(),
+        path,
+        body: snippet(body),
+        source,
+    })
+}
+
+/// The start of `body` with credentials, such as the tokens of a `/oauth2/token` response,
+/// redacted.
+fn snippet(body: &[u8]) -> String {
+    let body = redact_json(&String::from_utf8_lossy(body));
+    match body.char_indices().nth(BODY_SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Accounts {
+        accounts: Vec<Account>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Account {
+        number: String,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[allow(dead_code)]
+    struct Optional {
+        code: Option<u32>,
+        accounts: Option<Vec<Account>>,
+    }
+
+    fn url() -> Url {
+        Url::parse("https://api01.iq.questrade.com/v1/accounts").unwrap()
+    }
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn parse_reports_where_decoding_failed() {
+        let body = br#"{"accounts": [{"number": "1"}, {"number": 2}]}"#;
+        let err = parse::<Accounts>(StatusCode::OK, &HeaderMap::new(), &url(), body).unwrap_err();
+        match err {
+            QuestradeError::DecodeError {
+                endpoint,
+                path,
+                body,
+                ..
+            } => {
+                assert_eq!("/v1/accounts", endpoint);
+                assert_eq!("accounts[1].number", path);
+                assert!(body.starts_with(r#"{"accounts""#));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_redacts_credentials_in_bodies() {
+        let url = Url::parse("https://login.questrade.com/oauth2/token").unwrap();
+        let err = parse::<Accounts>(
+            StatusCode::OK,
+            &HeaderMap::new(),
+            &url,
+            br#"{"access_token":"a1","refresh_token":"r1","expires_in":"soon"}"#,
+        )
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("REDACTED"), "{}", message);
+        assert!(
+            !message.contains("a1") && !message.contains("r1"),
+            "{}",
+            message
+        );
+
+        let err = parse::<Accounts>(
+            StatusCode::BAD_GATEWAY,
+            &headers("text/plain"),
+            &url,
+            br#"upstream sent {"refresh_token":"r1""#,
+        )
+        .unwrap_err();
+        assert!(!err.to_string().contains("r1"), "{}", err);
+    }
+
+    #[test]
+    fn parse_distinguishes_api_and_http_errors() {
+        let err = parse::<Accounts>(
+            StatusCode::NOT_FOUND,
+            &headers("application/json; charset=utf-8"),
+            &url(),
+            br#"{"code": 1001, "message": "Invalid endpoint"}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, QuestradeError::ApiError(e) if e.code == 1001));
+
+        let body = "<html>Bad Gateway</html>".repeat(100);
+        let err = parse::<Accounts>(
+            StatusCode::BAD_GATEWAY,
+            &headers("text/html"),
+            &url(),
+            body.as_bytes(),
+        )
+        .unwrap_err();
+        assert!(err.is_retryable());
+        match err {
+            QuestradeError::HttpError { status, body, .. } => {
+                assert_eq!(StatusCode::BAD_GATEWAY, status);
+                assert_eq!(BODY_SNIPPET_LEN + 3, body.len());
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_never_decodes_error_statuses_as_data() {
+        let err = parse::<Optional>(
+            StatusCode::BAD_REQUEST,
+            &headers("application/json"),
+            &url(),
+            br#"{"code": "bad", "message": "not a questrade error"}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, QuestradeError::HttpError { .. }));
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn parse_handles_empty_and_non_json_bodies() {
+        parse::<()>(StatusCode::NO_CONTENT, &HeaderMap::new(), &url(), b"").unwrap();
+        assert!(parse::<Option<Accounts>>(
+            StatusCode::OK,
+            &headers("application/json"),
+            &url(),
+            b" "
+        )
+        .unwrap()
+        .is_none());
+
+        let err = parse::<Accounts>(StatusCode::OK, &headers("text/html"), &url(), b"<html>")
+            .unwrap_err();
+        assert!(
+            matches!(err, QuestradeError::UnexpectedContentType { content_type, .. } if content_type == "text/html")
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Method, StatusCode,
+};
+
+use crate::errors::QuestradeError;
+
+/// How [`Client`](crate::Client) retries requests that failed with a connect error, a timeout,
+/// a 5xx or a 429. Delays grow exponentially from `initial_backoff` up to `max_backoff`, unless
+/// the server asks for a specific delay with `Retry-After`. A `Retry-After` longer than
+/// `max_backoff` is not waited out, the request fails instead.
+///
+/// Only idempotent requests are retried unless `retry_non_idempotent` is set.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that sends every request exactly once.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Total number of attempts including the first, defaults to 3.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Randomizes each delay between half and all of the computed backoff, defaults to true.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    pub(crate) fn max_attempts_for(&self, method: &Method) -> u32 {
+        if self.retry_non_idempotent || is_idempotent(method) {
+            self.max_attempts
+        } else {
+            1
+        }
+    }
+
+    /// The delay before attempt `attempt + 1`, where `attempt` starts at 1, or `None` if the
+    /// server asked for a longer delay than `max_backoff`.
+    pub(crate) fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_backoff).then_some(retry_after);
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        if self.jitter && !backoff.is_zero() {
+            let half = backoff / 2;
+            Some(half + rand::thread_rng().gen_range(Duration::ZERO..=half))
+        } else {
+            Some(backoff)
+        }
+    }
+}
+
+/// The attempts at sending one request. Decides whether and when a failed attempt is retried, so
+/// the async and blocking clients only have to send requests and sleep.
+#[derive(Debug)]
+pub(crate) struct Attempts<'a> {
+    policy: &'a RetryPolicy,
+    attempt: u32,
+    max_attempts: u32,
+}
+
+impl<'a> Attempts<'a> {
+    pub(crate) fn new(policy: &'a RetryPolicy, method: &Method) -> Self {
+        Attempts {
+            policy,
+            attempt: 1,
+            max_attempts: policy.max_attempts_for(method),
+        }
+    }
+
+    /// The current attempt, starting at 1.
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether another attempt may follow the current one, in which case the client has to keep
+    /// a copy of the request.
+    pub(crate) fn may_retry(&self) -> bool {
+        self.attempt < self.max_attempts
+    }
+
+    /// Decides what follows the failed current attempt. Returns the copy of the request to send
+    /// next with the delay to wait before sending it, or the error to give up with.
+    pub(crate) fn retry<R>(
+        &mut self,
+        failure: Failure,
+        request: Option<R>,
+    ) -> Result<(R, Duration), QuestradeError> {
+        let Failure {
+            err,
+            retryable,
+            retry_after,
+        } = failure;
+        if !retryable {
+            return Err(err);
+        }
+        let (request, delay) = match (request, self.policy.backoff(self.attempt, retry_after)) {
+            (Some(request), Some(delay)) => (request, delay),
+            _ => return Err(err),
+        };
+        tracing::warn!(
+            attempt = self.attempt,
+            max_attempts = self.max_attempts,
+            ?delay,
+            error = %err,
+            "request failed, retrying"
+        );
+        self.attempt += 1;
+        Ok((request, delay))
+    }
+}
+
+/// Why an attempt failed, and whether that is worth retrying.
+#[derive(Debug)]
+pub(crate) struct Failure {
+    err: QuestradeError,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    /// No response was received, e.g. because the connection failed.
+    pub(crate) fn transport(err: QuestradeError) -> Self {
+        Failure {
+            retryable: err.is_retryable(),
+            err,
+            retry_after: None,
+        }
+    }
+
+    /// The server answered with `status` and `headers`, but the response was an error or could
+    /// not be read.
+    pub(crate) fn response(err: QuestradeError, status: StatusCode, headers: &HeaderMap) -> Self {
+        Failure {
+            err,
+            retryable: is_transient_status(status),
+            retry_after: retry_after(headers),
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Parses `Retry-After` given either as seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_max() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(350))
+            .jitter(false);
+        assert_eq!(Some(Duration::from_millis(100)), policy.backoff(1, None));
+        assert_eq!(Some(Duration::from_millis(200)), policy.backoff(2, None));
+        assert_eq!(Some(Duration::from_millis(350)), policy.backoff(3, None));
+        assert_eq!(Some(Duration::from_millis(350)), policy.backoff(40, None));
+    }
+
+    #[test]
+    fn backoff_honours_retry_after_up_to_max() {
+        let policy = RetryPolicy::default().max_backoff(Duration::from_secs(10));
+        assert_eq!(
+            Some(Duration::from_secs(7)),
+            policy.backoff(1, Some(Duration::from_secs(7)))
+        );
+        assert_eq!(
+            Some(Duration::from_secs(10)),
+            policy.backoff(1, Some(Duration::from_secs(10)))
+        );
+        assert_eq!(None, policy.backoff(1, Some(Duration::from_secs(11))));
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_bounds() {
+        let policy = RetryPolicy::default().initial_backoff(Duration::from_millis(100));
+        for _ in 0..100 {
+            let backoff = policy.backoff(2, None).unwrap();
+            assert!(backoff >= Duration::from_millis(100));
+            assert!(backoff <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn only_idempotent_methods_are_retried_by_default() {
+        let policy = RetryPolicy::default();
+        assert_eq!(3, policy.max_attempts_for(&Method::GET));
+        assert_eq!(3, policy.max_attempts_for(&Method::DELETE));
+        assert_eq!(1, policy.max_attempts_for(&Method::POST));
+        let policy = policy.retry_non_idempotent(true);
+        assert_eq!(3, policy.max_attempts_for(&Method::POST));
+        assert_eq!(1, RetryPolicy::none().max_attempts_for(&Method::GET));
+    }
+
+    #[test]
+    fn attempts_retry_transient_failures_only() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(100))
+            .jitter(false);
+        let mut attempts = Attempts::new(&policy, &Method::GET);
+        let unavailable = || {
+            Failure::response(
+                QuestradeError::InternalError(String::new()),
+                StatusCode::SERVICE_UNAVAILABLE,
+                &HeaderMap::new(),
+            )
+        };
+
+        assert!(attempts.may_retry());
+        let (_, delay) = attempts.retry(unavailable(), Some(())).unwrap();
+        assert_eq!(Duration::from_millis(100), delay);
+        assert_eq!(2, attempts.attempt());
+
+        let not_found = Failure::response(
+            QuestradeError::InternalError(String::new()),
+            StatusCode::NOT_FOUND,
+            &HeaderMap::new(),
+        );
+        assert!(attempts.retry(not_found, Some(())).is_err());
+        let refused = Failure::transport(QuestradeError::from(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )));
+        assert!(attempts.retry(refused, Some(())).is_err());
+        // Without a copy of the request there is nothing to send again.
+        assert!(attempts.retry::<()>(unavailable(), None).is_err());
+
+        attempts.retry(unavailable(), Some(())).unwrap();
+        assert!(!attempts.may_retry());
+    }
+
+    #[test]
+    fn retry_after_parse_works() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, retry_after(&headers));
+        headers.insert(RETRY_AFTER, "3".parse().unwrap());
+        assert_eq!(Some(Duration::from_secs(3)), retry_after(&headers));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(Some(Duration::ZERO), retry_after(&headers));
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(None, retry_after(&headers));
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    accounts::{Account, Activity, Balances, Execution, Order, Position},
+    client::Time,
+    errors::QuestradeError,
+    markets::{Candle, Market, Quote},
+    options::OptionChain,
+    symbols::{EquitySymbol, SymbolDetails},
+    Interval, Session, StateFilter,
+};
+
+/// A call to one of the API endpoints, as accepted by [`QuestradeService`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuestradeRequest {
+    Time,
+    Accounts,
+    AccountActivities {
+        account_id: String,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    AccountBalances {
+        account_id: String,
+    },
+    AccountPositions {
+        account_id: String,
+    },
+    AccountExecutions {
+        account_id: String,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+    },
+    AccountOrders {
+        account_id: String,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    },
+    AccountOrder {
+        account_id: String,
+        order_id: i64,
+    },
+    MarketCandles {
+        symbol_id: i64,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        interval: Interval,
+    },
+    MarketQuotesSymbol {
+        symbol_id: i64,
+    },
+    MarketQuotesSymbols {
+        symbol_ids: Vec<i64>,
+    },
+    Markets,
+    Symbol {
+        symbol_id: i64,
+    },
+    SymbolOptions {
+        symbol_id: i64,
+    },
+    SymbolsByIds {
+        symbol_ids: Vec<i64>,
+    },
+    SymbolsByNames {
+        names: Vec<String>,
+    },
+    SymbolSearch {
+        prefix: String,
+        offset: u32,
+    },
+}
+
+/// The typed result of a [`QuestradeRequest`], with one variant per request variant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuestradeResponse {
+    Time(Time),
+    Accounts(Vec<Account>),
+    AccountActivities(Vec<Activity>),
+    AccountBalances(Balances),
+    AccountPositions(Vec<Position>),
+    AccountExecutions(Vec<Execution>),
+    AccountOrders(Vec<Order>),
+    AccountOrder(Box<Order>),
+    MarketCandles(Vec<Candle>),
+    MarketQuotesSymbol(Vec<Quote>),
+    MarketQuotesSymbols(Vec<Quote>),
+    Markets(Vec<Market>),
+    Symbol(Box<SymbolDetails>),
+    SymbolOptions(OptionChain),
+    SymbolsByIds(Vec<SymbolDetails>),
+    SymbolsByNames(Vec<SymbolDetails>),
+    SymbolSearch(Vec<EquitySymbol>),
+}
+
+/// Exposes a [`Session`] as a [`tower::Service`], so standard layers such as timeouts,
+/// concurrency limits and buffering can be put around it. Cloning is cheap and clones share
+/// the session.
+#[derive(Debug, Clone)]
+pub struct QuestradeService {
+    session: Arc<Session>,
+}
+
+impl QuestradeService {
+    pub fn new(session: Arc<Session>) -> Self {
+        QuestradeService { session }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl From<Session> for QuestradeService {
+    fn from(session: Session) -> Self {
+        QuestradeService::new(Arc::new(session))
+    }
+}
+
+impl tower::Service<QuestradeRequest> for QuestradeService {
+    type Response = QuestradeResponse;
+    type Error = QuestradeError;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<QuestradeResponse, QuestradeError>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: QuestradeRequest) -> Self::Future {
+        let session = self.session.clone();
+        Box::pin(async move { call(&session, request).await })
+    }
+}
+
+async fn call(
+    session: &Session,
+    request: QuestradeRequest,
+) -> Result<QuestradeResponse, QuestradeError> {
+    use QuestradeRequest as Req;
+    use QuestradeResponse as Res;
+
+    Ok(match request {
+        Req::Time => Res::Time(session.time().await?),
+        Req::Accounts => Res::Accounts(session.accounts().await?),
+        Req::AccountActivities {
+            account_id,
+            start,
+            end,
+        } => Res::AccountActivities(
+            session
+                .account_activities(&account_id, &start, &end)
+                .await?,
+        ),
+        Req::AccountBalances { account_id } => {
+            Res::AccountBalances(session.account_balances(&account_id).await?)
+        }
+        Req::AccountPositions { account_id } => {
+            Res::AccountPositions(session.account_positions(&account_id).await?)
+        }
+        Req::AccountExecutions {
+            account_id,
+            start,
+            end,
+        } => Res::AccountExecutions(
+            session
+                .account_executions(&account_id, start.as_ref(), end.as_ref())
+                .await?,
+        ),
+        Req::AccountOrders {
+            account_id,
+            start,
+            end,
+            state_filter,
+        } => Res::AccountOrders(
+            session
+                .account_orders(&account_id, start.as_ref(), end.as_ref(), state_filter)
+                .await?,
+        ),
+        Req::AccountOrder {
+            account_id,
+            order_id,
+        } => Res::AccountOrder(Box::new(
+            session.account_order(&account_id, order_id).await?,
+        )),
+        Req::MarketCandles {
+            symbol_id,
+            start,
+            end,
+            interval,
+        } => Res::MarketCandles(
+            session
+                .market_candles(symbol_id, &start, &end, interval)
+                .await?,
+        ),
+        Req::MarketQuotesSymbol { symbol_id } => {
+            Res::MarketQuotesSymbol(session.market_quotes_symbol(symbol_id).await?)
+        }
+        Req::MarketQuotesSymbols { symbol_ids } => {
+            Res::MarketQuotesSymbols(session.market_quotes_symbols(symbol_ids).await?)
+        }
+        Req::Markets => Res::Markets(session.markets().await?),
+        Req::Symbol { symbol_id } => Res::Symbol(Box::new(session.symbol(symbol_id).await?)),
+        Req::SymbolOptions { symbol_id } => {
+            Res::SymbolOptions(session.symbol_options(symbol_id).await?)
+        }
+        Req::SymbolsByIds { symbol_ids } => {
+            Res::SymbolsByIds(session.symbols_by_ids(symbol_ids).await?)
+        }
+        Req::SymbolsByNames { names } => {
+            Res::SymbolsByNames(session.symbols_by_names(names).await?)
+        }
+        Req::SymbolSearch { prefix, offset } => {
+            Res::SymbolSearch(session.symbol_search(&prefix, offset).await?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tower::{Service, ServiceBuilder, ServiceExt};
+
+    use super::*;
+    use crate::test_server::{test_client, FakeTokens, TestResponse, TestServer};
+
+    fn service(api_server: &str) -> QuestradeService {
+        Session::new(test_client(), Arc::new(FakeTokens::new(api_server))).into()
+    }
+
+    #[tokio::test]
+    async fn service_calls_endpoint() {
+        let server = TestServer::start(|_| TestResponse::json(200, r#"{"markets": []}"#)).await;
+
+        let response = service(&server.url)
+            .oneshot(QuestradeRequest::Markets)
+            .await
+            .unwrap();
+        assert_eq!(QuestradeResponse::Markets(Vec::new()), response);
+        assert_eq!("/v1/markets", server.requests()[0].target);
+    }
+
+    #[tokio::test]
+    async fn service_works_with_tower_layers() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(200, r#"{"time": "2014-10-24T12:14:42.730000-04:00"}"#)
+        })
+        .await;
+        let mut service = ServiceBuilder::new()
+            .concurrency_limit(1)
+            .timeout(Duration::from_secs(5))
+            .service(service(&server.url));
+
+        for _ in 0..2 {
+            let response = service
+                .ready()
+                .await
+                .unwrap()
+                .call(QuestradeRequest::Time)
+                .await
+                .unwrap();
+            assert!(matches!(response, QuestradeResponse::Time(_)));
+        }
+        assert_eq!(2, server.requests().len());
+    }
+}
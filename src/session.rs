@@ -0,0 +1,422 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    accounts::{Account, Activity, Balances, Execution, Order, Position},
+    auth::ApiToken,
+    client::{Client, Time},
+    errors::QuestradeError,
+    markets::{Candle, Market, Quote},
+    options::OptionChain,
+    resolver::{Lookup, ResolvedSymbol, SymbolQuery, SymbolResolver},
+    response::Response,
+    symbols::{EquitySymbol, SymbolDetails},
+    token::{TokenManager, TokenSource},
+    Interval, StateFilter,
+};
+
+/// Runs `$call` with a valid token bound to `$token`, refreshing the token and replaying the
+/// call once if the API rejects it.
+macro_rules! with_token {
+    ($session:expr, |$token:ident| $call:expr) => {{
+        let $token = $session.token().await?;
+        match $call.await {
+            Err(err) if err.is_auth_error() => {
+                tracing::debug!("access token rejected, refreshing: {}", err);
+                let $token = $session
+                    .tokens
+                    .refresh(&$session.client, &$token.access_token)
+                    .await?;
+                $call.await
+            }
+            result => result,
+        }
+    }};
+}
+
+/// A [`Client`] bound to a [`TokenSource`], exposing the same endpoints without threading an
+/// [`ApiToken`] through every call. Tokens are refreshed before they expire and again if the API
+/// rejects them.
+#[derive(Debug)]
+pub struct Session {
+    client: Client,
+    tokens: Arc<dyn TokenSource>,
+}
+
+impl Session {
+    pub fn new(client: Client, tokens: Arc<dyn TokenSource>) -> Self {
+        Session { client, tokens }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub async fn token(&self) -> Result<ApiToken, QuestradeError> {
+        self.tokens.token(&self.client).await
+    }
+
+    pub async fn time(&self) -> Result<Time, QuestradeError> {
+        with_token!(self, |token| self.client.time(&token))
+    }
+
+    pub async fn time_with_meta(&self) -> Result<Response<Time>, QuestradeError> {
+        with_token!(self, |token| self.client.time_with_meta(&token))
+    }
+
+    pub async fn accounts(&self) -> Result<Vec<Account>, QuestradeError> {
+        with_token!(self, |token| self.client.accounts(&token))
+    }
+
+    pub async fn accounts_with_meta(&self) -> Result<Response<Vec<Account>>, QuestradeError> {
+        with_token!(self, |token| self.client.accounts_with_meta(&token))
+    }
+
+    pub async fn account_activities(
+        &self,
+        account_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Activity>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_activities(&token, account_id, start, end))
+    }
+
+    pub async fn account_activities_with_meta(
+        &self,
+        account_id: &str,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Response<Vec<Activity>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_activities_with_meta(&token, account_id, start, end))
+    }
+
+    pub async fn account_balances(&self, account_id: &str) -> Result<Balances, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_balances(&token, account_id))
+    }
+
+    pub async fn account_balances_with_meta(
+        &self,
+        account_id: &str,
+    ) -> Result<Response<Balances>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_balances_with_meta(&token, account_id))
+    }
+
+    pub async fn account_positions(
+        &self,
+        account_id: &str,
+    ) -> Result<Vec<Position>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_positions(&token, account_id))
+    }
+
+    pub async fn account_positions_with_meta(
+        &self,
+        account_id: &str,
+    ) -> Result<Response<Vec<Position>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_positions_with_meta(&token, account_id))
+    }
+
+    pub async fn account_executions(
+        &self,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+    ) -> Result<Vec<Execution>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_executions(&token, account_id, start, end))
+    }
+
+    pub async fn account_executions_with_meta(
+        &self,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+    ) -> Result<Response<Vec<Execution>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_executions_with_meta(&token, account_id, start, end))
+    }
+
+    pub async fn account_orders(
+        &self,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    ) -> Result<Vec<Order>, QuestradeError> {
+        with_token!(self, |token| self.client.account_orders(
+            &token,
+            account_id,
+            start,
+            end,
+            state_filter.clone()
+        ))
+    }
+
+    pub async fn account_orders_with_meta(
+        &self,
+        account_id: &str,
+        start: Option<&DateTime<Utc>>,
+        end: Option<&DateTime<Utc>>,
+        state_filter: Option<StateFilter>,
+    ) -> Result<Response<Vec<Order>>, QuestradeError> {
+        with_token!(self, |token| self.client.account_orders_with_meta(
+            &token,
+            account_id,
+            start,
+            end,
+            state_filter.clone()
+        ))
+    }
+
+    pub async fn account_order(
+        &self,
+        account_id: &str,
+        order_id: i64,
+    ) -> Result<Order, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_order(&token, account_id, order_id))
+    }
+
+    pub async fn account_order_with_meta(
+        &self,
+        account_id: &str,
+        order_id: i64,
+    ) -> Result<Response<Order>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .account_order_with_meta(&token, account_id, order_id))
+    }
+
+    pub async fn market_candles(
+        &self,
+        symbol_id: i64,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        interval: Interval,
+    ) -> Result<Vec<Candle>, QuestradeError> {
+        with_token!(self, |token| self.client.market_candles(
+            &token,
+            symbol_id,
+            start,
+            end,
+            interval.clone()
+        ))
+    }
+
+    pub async fn market_candles_with_meta(
+        &self,
+        symbol_id: i64,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        interval: Interval,
+    ) -> Result<Response<Vec<Candle>>, QuestradeError> {
+        with_token!(self, |token| self.client.market_candles_with_meta(
+            &token,
+            symbol_id,
+            start,
+            end,
+            interval.clone()
+        ))
+    }
+
+    pub async fn market_quotes_symbol(&self, symbol_id: i64) -> Result<Vec<Quote>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .market_quotes_symbol(&token, symbol_id))
+    }
+
+    pub async fn market_quotes_symbol_with_meta(
+        &self,
+        symbol_id: i64,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .market_quotes_symbol_with_meta(&token, symbol_id))
+    }
+
+    pub async fn market_quotes_symbols(
+        &self,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Vec<Quote>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .market_quotes_symbols(&token, symbol_ids.clone()))
+    }
+
+    pub async fn market_quotes_symbols_with_meta(
+        &self,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Response<Vec<Quote>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .market_quotes_symbols_with_meta(&token, symbol_ids.clone()))
+    }
+
+    pub async fn markets(&self) -> Result<Vec<Market>, QuestradeError> {
+        with_token!(self, |token| self.client.markets(&token))
+    }
+
+    pub async fn markets_with_meta(&self) -> Result<Response<Vec<Market>>, QuestradeError> {
+        with_token!(self, |token| self.client.markets_with_meta(&token))
+    }
+
+    pub async fn symbol(&self, symbol_id: i64) -> Result<SymbolDetails, QuestradeError> {
+        with_token!(self, |token| self.client.symbol(&token, symbol_id))
+    }
+
+    pub async fn symbol_with_meta(
+        &self,
+        symbol_id: i64,
+    ) -> Result<Response<SymbolDetails>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbol_with_meta(&token, symbol_id))
+    }
+
+    pub async fn symbol_options(&self, symbol_id: i64) -> Result<OptionChain, QuestradeError> {
+        with_token!(self, |token| self.client.symbol_options(&token, symbol_id))
+    }
+
+    pub async fn symbol_options_with_meta(
+        &self,
+        symbol_id: i64,
+    ) -> Result<Response<OptionChain>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbol_options_with_meta(&token, symbol_id))
+    }
+
+    pub async fn symbols_by_ids(
+        &self,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbols_by_ids(&token, symbol_ids.clone()))
+    }
+
+    pub async fn symbols_by_names(
+        &self,
+        names: Vec<String>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbols_by_names(&token, names.clone()))
+    }
+
+    pub async fn symbol_search(
+        &self,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbol_search(&token, prefix, offset))
+    }
+
+    pub async fn symbol_search_with_meta(
+        &self,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Response<Vec<EquitySymbol>>, QuestradeError> {
+        with_token!(self, |token| self
+            .client
+            .symbol_search_with_meta(&token, prefix, offset))
+    }
+
+    pub async fn symbol_search_all(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        with_token!(self, |token| self.client.symbol_search_all(&token, prefix))
+    }
+
+    /// Resolves `query` with `resolver`, like [`SymbolResolver::resolve`].
+    pub async fn resolve_symbol<Q>(
+        &self,
+        resolver: &SymbolResolver,
+        query: Q,
+    ) -> Result<ResolvedSymbol, QuestradeError>
+    where
+        Q: Into<SymbolQuery>,
+    {
+        resolver
+            .resolve_with(&Lookup::Session(self), vec![query.into()])
+            .await?
+            .remove(0)
+    }
+
+    /// Resolves every query with `resolver`, like [`SymbolResolver::resolve_all`].
+    pub async fn resolve_symbols(
+        &self,
+        resolver: &SymbolResolver,
+        queries: Vec<SymbolQuery>,
+    ) -> Result<Vec<Result<ResolvedSymbol, QuestradeError>>, QuestradeError> {
+        resolver.resolve_with(&Lookup::Session(self), queries).await
+    }
+}
+
+impl Client {
+    /// Binds this client to `token`, which is kept fresh by a [`TokenManager`].
+    pub fn session(self, token: ApiToken) -> Session {
+        Session::new(self, Arc::new(TokenManager::new(token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        errors::ApiErrorCode,
+        test_server::{test_client, FakeTokens, TestResponse, TestServer},
+    };
+
+    #[tokio::test]
+    async fn session_refreshes_rejected_token() {
+        let server = TestServer::start(|request| match request.header("authorization") {
+            Some("Bearer a2") => TestResponse::json(200, r#"{"accounts": []}"#),
+            _ => TestResponse::json(
+                401,
+                r#"{"code": 1017, "message": "Access token is invalid"}"#,
+            ),
+        })
+        .await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let session = Session::new(test_client(), tokens.clone());
+
+        assert_eq!(Vec::<Account>::new(), session.accounts().await.unwrap());
+        assert_eq!(1, tokens.refreshes());
+        let requests = server.requests();
+        assert_eq!(2, requests.len());
+        assert_eq!("/v1/accounts", requests[1].target);
+    }
+
+    #[tokio::test]
+    async fn session_does_not_refresh_on_other_errors() {
+        let server = TestServer::start(|_| {
+            TestResponse::json(404, r#"{"code": 1001, "message": "Invalid endpoint"}"#)
+        })
+        .await;
+        let tokens = Arc::new(FakeTokens::new(&server.url));
+        let session = Session::new(test_client(), tokens.clone());
+
+        let err = session.markets().await.unwrap_err();
+        assert_eq!(Some(ApiErrorCode::InvalidEndpoint), err.api_error_code());
+        assert_eq!(0, tokens.refreshes());
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth::ApiToken, errors::QuestradeError};
+
+use super::{read_optional, write_atomic, StoreLock, TokenStore};
+
+const VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+/// Upper bounds on the Argon2 cost parameters, in KiB, passes and lanes. They are checked on
+/// load before deriving the key, so a corrupted or tampered file header can't make loading
+/// allocate gigabytes or run for minutes.
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 10;
+const MAX_P_COST: u32 = 8;
+
+/// Checks the Argon2 cost parameters against the bounds above.
+fn check_costs(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<(), String> {
+    if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(format!(
+            "Argon2 costs m={} t={} p={} exceed the maximum of m={} t={} p={}",
+            m_cost, t_cost, p_cost, MAX_M_COST, MAX_T_COST, MAX_P_COST
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Envelope {
+    version: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl Envelope {
+    /// The header fields are authenticated along with the ciphertext, so any modification of
+    /// the file is detected on load.
+    fn associated_data(&self) -> Vec<u8> {
+        format!(
+            "questrade-token:v{}:{}:{}:{}:{}",
+            self.version, self.m_cost, self.t_cost, self.p_cost, self.salt
+        )
+        .into_bytes()
+    }
+}
+
+/// Stores the token encrypted with XChaCha20-Poly1305 under a key derived from a passphrase with
+/// Argon2id. The file is written with `0600` permissions and, on unix, refused on load if it is
+/// readable by group or others.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    passphrase: String,
+    params: Params,
+}
+
+impl fmt::Debug for EncryptedFileStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedFileStore")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedFileStore {
+    pub fn new<P: AsRef<Path>>(path: P, passphrase: &str) -> Self {
+        EncryptedFileStore {
+            path: path.as_ref().to_path_buf(),
+            passphrase: String::from(passphrase),
+            params: Params::default(),
+        }
+    }
+
+    /// Overrides the Argon2 cost parameters used when saving. Loading always uses the parameters
+    /// recorded in the file. Memory is capped at 256 MiB, passes at 10 and lanes at 8.
+    pub fn kdf_params(
+        mut self,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> Result<Self, QuestradeError> {
+        check_costs(m_cost, t_cost, p_cost).map_err(QuestradeError::Builder)?;
+        self.params = Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN))
+            .map_err(|err| QuestradeError::Builder(err.to_string()))?;
+        Ok(self)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn derive_key(&self, params: Params, salt: &[u8]) -> Result<Key, QuestradeError> {
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| QuestradeError::DecryptionError(err.to_string()))?;
+        Ok(key)
+    }
+
+    #[cfg(unix)]
+    fn check_permissions(&self) -> Result<(), QuestradeError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&self.path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(QuestradeError::StoreError(format!(
+                "{} is accessible by other users (mode {:o}), expected 600",
+                self.path.display(),
+                mode & 0o777
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn check_permissions(&self) -> Result<(), QuestradeError> {
+        Ok(())
+    }
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, QuestradeError> {
+    STANDARD
+        .decode(value)
+        .map_err(|err| QuestradeError::DecryptionError(format!("invalid {}: {}", field, err)))
+}
+
+impl TokenStore for EncryptedFileStore {
+    fn load(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        let contents = match read_optional(&self.path)? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+        self.check_permissions()?;
+
+        let envelope: Envelope = serde_json::from_slice(&contents).map_err(|err| {
+            QuestradeError::DecryptionError(format!("malformed token file: {}", err))
+        })?;
+        if envelope.version != VERSION {
+            return Err(QuestradeError::DecryptionError(format!(
+                "unsupported token file version {}",
+                envelope.version
+            )));
+        }
+
+        check_costs(envelope.m_cost, envelope.t_cost, envelope.p_cost)
+            .map_err(QuestradeError::StoreError)?;
+        let params = Params::new(
+            envelope.m_cost,
+            envelope.t_cost,
+            envelope.p_cost,
+            Some(KEY_LEN),
+        )
+        .map_err(|err| QuestradeError::DecryptionError(err.to_string()))?;
+        let key = self.derive_key(params, &decode("salt", &envelope.salt)?)?;
+        let nonce = decode("nonce", &envelope.nonce)?;
+        if nonce.len() != 24 {
+            return Err(QuestradeError::DecryptionError(String::from(
+                "invalid nonce length",
+            )));
+        }
+
+        let plaintext = XChaCha20Poly1305::new(&key)
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &decode("ciphertext", &envelope.ciphertext)?,
+                    aad: &envelope.associated_data(),
+                },
+            )
+            .map_err(|_| {
+                QuestradeError::DecryptionError(String::from(
+                    "wrong passphrase or token file has been tampered with",
+                ))
+            })?;
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|err| QuestradeError::DecryptionError(err.to_string()))
+    }
+
+    fn save(&self, token: &ApiToken) -> Result<(), QuestradeError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        let mut envelope = Envelope {
+            version: VERSION,
+            m_cost: self.params.m_cost(),
+            t_cost: self.params.t_cost(),
+            p_cost: self.params.p_cost(),
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: String::new(),
+        };
+
+        let key = self.derive_key(self.params.clone(), &salt)?;
+        let plaintext =
+            serde_json::to_vec(token).map_err(|err| QuestradeError::StoreError(err.to_string()))?;
+        let ciphertext = XChaCha20Poly1305::new(&key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &envelope.associated_data(),
+                },
+            )
+            .map_err(|err| QuestradeError::StoreError(err.to_string()))?;
+        envelope.ciphertext = STANDARD.encode(ciphertext);
+
+        let contents = serde_json::to_vec_pretty(&envelope)
+            .map_err(|err| QuestradeError::StoreError(err.to_string()))?;
+        write_atomic(&self.path, &contents)
+    }
+
+    fn lock(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        StoreLock::acquire(&self.path).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::test_token;
+
+    fn store(path: &Path, passphrase: &str) -> EncryptedFileStore {
+        EncryptedFileStore::new(path, passphrase)
+            .kdf_params(64, 1, 1)
+            .unwrap()
+    }
+
+    fn token() -> ApiToken {
+        test_token("access", "https://api01.iq.questrade.com/")
+    }
+
+    #[test]
+    fn encrypted_file_store_roundtrip_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        let store = store(&path, "hunter2");
+        assert_eq!(None, store.load().unwrap());
+
+        let token = token();
+        store.save(&token).unwrap();
+        assert_eq!(Some(token), store.load().unwrap());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("refresh"));
+    }
+
+    #[test]
+    fn encrypted_file_store_rejects_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        store(&path, "hunter2").save(&token()).unwrap();
+
+        let err = store(&path, "hunter3").load().unwrap_err();
+        assert!(matches!(err, QuestradeError::DecryptionError(_)));
+    }
+
+    #[test]
+    fn encrypted_file_store_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        let store = store(&path, "hunter2");
+        store.save(&token()).unwrap();
+
+        let mut envelope: Envelope =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let mut ciphertext = STANDARD.decode(&envelope.ciphertext).unwrap();
+        ciphertext[0] ^= 0x01;
+        envelope.ciphertext = STANDARD.encode(ciphertext);
+        write_atomic(&path, &serde_json::to_vec(&envelope).unwrap()).unwrap();
+
+        let err = store.load().unwrap_err();
+        assert!(matches!(err, QuestradeError::DecryptionError(_)));
+    }
+
+    #[test]
+    fn encrypted_file_store_detects_header_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        let store = store(&path, "hunter2");
+        store.save(&token()).unwrap();
+
+        let mut envelope: Envelope =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        envelope.t_cost += 1;
+        write_atomic(&path, &serde_json::to_vec(&envelope).unwrap()).unwrap();
+
+        let err = store.load().unwrap_err();
+        assert!(matches!(err, QuestradeError::DecryptionError(_)));
+    }
+
+    #[test]
+    fn encrypted_file_store_bounds_kdf_costs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        let store = store(&path, "hunter2");
+        store.save(&token()).unwrap();
+
+        let mut envelope: Envelope =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        envelope.m_cost = u32::MAX;
+        write_atomic(&path, &serde_json::to_vec(&envelope).unwrap()).unwrap();
+
+        let err = store.load().unwrap_err();
+        assert!(matches!(err, QuestradeError::StoreError(_)));
+        assert!(matches!(
+            EncryptedFileStore::new(&path, "hunter2").kdf_params(64, MAX_T_COST + 1, 1),
+            Err(QuestradeError::Builder(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn encrypted_file_store_rejects_open_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.enc");
+        let store = store(&path, "hunter2");
+        store.save(&token()).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let err = store.load().unwrap_err();
+        assert!(matches!(err, QuestradeError::StoreError(_)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::{auth::ApiToken, errors::QuestradeError};
+
+use super::{read_optional, write_atomic, StoreLock, TokenStore};
+
+const QT_ACCESS_TOKEN: &str = "QT_ACCESS_TOKEN";
+const QT_API_SERVER: &str = "QT_API_SERVER";
+const QT_EXPIRES_IN: &str = "QT_EXPIRES_IN";
+const QT_ISSUED_AT: &str = "QT_ISSUED_AT";
+const QT_REFRESH_TOKEN: &str = "QT_REFRESH_TOKEN";
+const QT_TOKEN_TYPE: &str = "QT_TOKEN_TYPE";
+
+/// Stores the token as `export QT_...=...` lines in a `.env` style file. Token lines are updated in
+/// place, and every other line, such as `QT_CONSUMER_KEY`, is kept as it was.
+///
+/// A file holding only `QT_REFRESH_TOKEN` loads as an already expired token, so the first call
+/// through a [`TokenManager`](crate::token::TokenManager) refreshes it.
+#[derive(Debug, Clone)]
+pub struct EnvFileStore {
+    path: PathBuf,
+}
+
+impl EnvFileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        EnvFileStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_lines(&self) -> Result<Vec<String>, QuestradeError> {
+        Ok(read_optional(&self.path)?
+            .map(|contents| {
+                String::from_utf8_lossy(&contents)
+                    .lines()
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line.split_once('=')?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((key.trim(), value))
+}
+
+impl TokenStore for EnvFileStore {
+    fn load(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        let lines = self.read_lines()?;
+        let get = |name: &str| {
+            lines
+                .iter()
+                .rev()
+                .filter_map(|line| parse_line(line))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| String::from(value))
+        };
+
+        let refresh_token = match get(QT_REFRESH_TOKEN) {
+            Some(refresh_token) => refresh_token,
+            None => return Ok(None),
+        };
+        Ok(Some(ApiToken {
+            access_token: get(QT_ACCESS_TOKEN).unwrap_or_default(),
+            token_type: get(QT_TOKEN_TYPE).unwrap_or_else(|| String::from("Bearer")),
+            refresh_token,
+            api_server: get(QT_API_SERVER).unwrap_or_default(),
+            expires_in: get(QT_EXPIRES_IN).and_then(|v| v.parse().ok()).unwrap_or(0),
+            issued_at: get(QT_ISSUED_AT)
+                .and_then(|v| DateTime::parse_from_rfc3339(&v).ok())
+                .map(|v| v.with_timezone(&Utc))
+                .unwrap_or_else(|| DateTime::<Utc>::from(std::time::UNIX_EPOCH)),
+        }))
+    }
+
+    fn save(&self, token: &ApiToken) -> Result<(), QuestradeError> {
+        let values = [
+            (QT_ACCESS_TOKEN, token.access_token.clone()),
+            (QT_API_SERVER, token.api_server.clone()),
+            (QT_EXPIRES_IN, token.expires_in.to_string()),
+            (QT_ISSUED_AT, token.issued_at.to_rfc3339()),
+            (QT_REFRESH_TOKEN, token.refresh_token.clone()),
+            (QT_TOKEN_TYPE, token.token_type.clone()),
+        ];
+
+        let mut written: Vec<&str> = Vec::new();
+        let mut lines: Vec<String> = Vec::new();
+        for line in self.read_lines()? {
+            let value =
+                parse_line(&line).and_then(|(key, _)| values.iter().find(|(name, _)| *name == key));
+            match value {
+                Some((name, value)) => {
+                    // Later duplicates are dropped, load would have ignored all but the last.
+                    if !written.contains(name) {
+                        lines.push(format!("export {}={}", name, value));
+                        written.push(name);
+                    }
+                }
+                None => lines.push(line),
+            }
+        }
+        lines.extend(
+            values
+                .iter()
+                .filter(|(name, _)| !written.contains(name))
+                .map(|(name, value)| format!("export {}={}", name, value)),
+        );
+
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        write_atomic(&self.path, contents.as_bytes())
+    }
+
+    fn lock(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        StoreLock::acquire(&self.path).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::test_token;
+
+    #[test]
+    fn env_file_store_roundtrip_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(&path, "export QT_CONSUMER_KEY=consumer\n").unwrap();
+
+        let store = EnvFileStore::new(&path);
+        assert_eq!(None, store.load().unwrap());
+
+        // RFC 3339 round trips only whole seconds.
+        let token = ApiToken {
+            issued_at: DateTime::parse_from_rfc3339("2021-11-14T17:20:40+00:00")
+                .unwrap()
+                .with_timezone(&Utc),
+            ..test_token("access", "https://api01.iq.questrade.com/")
+        };
+        store.save(&token).unwrap();
+        store.save(&token).unwrap();
+        assert_eq!(Some(token), store.load().unwrap());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("export QT_CONSUMER_KEY=consumer\n"));
+        assert_eq!(1, contents.matches("QT_REFRESH_TOKEN").count());
+    }
+
+    #[test]
+    fn env_file_store_keeps_other_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "# questrade\n\nexport QT_CONSUMER_KEY=consumer\nexport QT_REFRESH_TOKEN=old\n\n  # end\n",
+        )
+        .unwrap();
+
+        let store = EnvFileStore::new(&path);
+        let mut token = store.load().unwrap().unwrap();
+        token.refresh_token = String::from("new");
+        store.save(&token).unwrap();
+        store.save(&token).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(
+            vec![
+                "# questrade",
+                "",
+                "export QT_CONSUMER_KEY=consumer",
+                "export QT_REFRESH_TOKEN=new",
+                "",
+                "  # end"
+            ],
+            lines[..6]
+        );
+        assert_eq!(1, contents.matches("QT_REFRESH_TOKEN").count());
+        assert_eq!("new", store.load().unwrap().unwrap().refresh_token);
+    }
+
+    #[test]
+    fn env_file_store_loads_refresh_token_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            "\nexport QT_CONSUMER_KEY=consumer\nexport QT_REFRESH_TOKEN=\"refresh\"\n",
+        )
+        .unwrap();
+
+        let token = EnvFileStore::new(&path).load().unwrap().unwrap();
+        assert_eq!("refresh", token.refresh_token);
+        assert!(token.expires_within(std::time::Duration::from_secs(0)));
+    }
+}
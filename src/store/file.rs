@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use crate::{auth::ApiToken, errors::QuestradeError};
+
+use super::{read_optional, write_atomic, StoreLock, TokenStore};
+
+/// Stores the token as JSON, replacing the file atomically on every save.
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        JsonFileStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TokenStore for JsonFileStore {
+    fn load(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        read_optional(&self.path)?
+            .map(|contents| {
+                serde_json::from_slice(&contents).map_err(|err| {
+                    QuestradeError::StoreError(format!(
+                        "failed to parse {}: {}",
+                        self.path.display(),
+                        err
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    fn save(&self, token: &ApiToken) -> Result<(), QuestradeError> {
+        let contents = serde_json::to_vec_pretty(token)
+            .map_err(|err| QuestradeError::StoreError(err.to_string()))?;
+        write_atomic(&self.path, &contents)
+    }
+
+    fn lock(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        StoreLock::acquire(&self.path).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::test_token;
+
+    #[test]
+    fn json_file_store_roundtrip_works() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JsonFileStore::new(dir.path().join("token.json"));
+        assert_eq!(None, store.load().unwrap());
+
+        let token = test_token("access", "https://api01.iq.questrade.com/");
+        store.save(&token).unwrap();
+        assert_eq!(Some(token), store.load().unwrap());
+    }
+
+    #[test]
+    fn json_file_store_rejects_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        let err = JsonFileStore::new(path).load().unwrap_err();
+        assert!(matches!(err, QuestradeError::StoreError(_)));
+    }
+}
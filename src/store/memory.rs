@@ -0,0 +1,50 @@
+use std::sync::Mutex;
+
+use crate::{auth::ApiToken, errors::QuestradeError};
+
+use super::TokenStore;
+
+/// Keeps the token in memory only, useful for tests and short lived processes.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    token: Mutex<Option<ApiToken>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    pub fn with_token(token: ApiToken) -> Self {
+        MemoryStore {
+            token: Mutex::new(Some(token)),
+        }
+    }
+}
+
+impl TokenStore for MemoryStore {
+    fn load(&self) -> Result<Option<ApiToken>, QuestradeError> {
+        Ok(self.token.lock().unwrap().clone())
+    }
+
+    fn save(&self, token: &ApiToken) -> Result<(), QuestradeError> {
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::test_token;
+
+    #[test]
+    fn memory_store_roundtrip_works() {
+        let store = MemoryStore::new();
+        assert_eq!(None, store.load().unwrap());
+
+        let token = test_token("access", "https://api01.iq.questrade.com/");
+        store.save(&token).unwrap();
+        assert_eq!(Some(token), store.load().unwrap());
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    ffi::OsString,
+    fmt::Debug,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use fs4::fs_std::FileExt;
+use uuid::Uuid;
+
+use crate::{auth::ApiToken, errors::QuestradeError};
+
+mod encrypted;
+mod env;
+mod file;
+mod memory;
+
+pub use encrypted::EncryptedFileStore;
+pub use env::EnvFileStore;
+pub use file::JsonFileStore;
+pub use memory::MemoryStore;
+
+/// Persists tokens across process restarts. Every refresh invalidates the previous refresh
+/// token, so a [`Client`](crate::Client) configured with a store saves each new token to it
+/// before handing the token back to the caller.
+pub trait TokenStore: Debug + Send + Sync {
+    fn load(&self) -> Result<Option<ApiToken>, QuestradeError>;
+
+    fn save(&self, token: &ApiToken) -> Result<(), QuestradeError>;
+
+    /// Takes an exclusive lock shared with other processes using the same store, blocking until
+    /// it is available. The client holds it across `load`, the refresh request and `save`, so
+    /// only one process rotates the refresh token at a time. Stores that are not shared return
+    /// `None`.
+    fn lock(&self) -> Result<Option<StoreLock>, QuestradeError> {
+        Ok(None)
+    }
+}
+
+/// An advisory lock on a token file, released when dropped.
+#[derive(Debug)]
+pub struct StoreLock {
+    file: fs::File,
+    path: PathBuf,
+}
+
+impl StoreLock {
+    /// Locks a `.lock` file next to `path`. The token file itself is replaced on every save, so
+    /// it can't carry the lock.
+    pub fn acquire(path: &Path) -> Result<Self, QuestradeError> {
+        let mut lock_path = OsString::from(path.as_os_str());
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let to_error = |err: std::io::Error| {
+            QuestradeError::StoreError(format!("failed to lock {}: {}", lock_path.display(), err))
+        };
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&lock_path).map_err(to_error)?;
+        FileExt::lock_exclusive(&file).map_err(to_error)?;
+
+        Ok(StoreLock {
+            file,
+            path: lock_path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        if let Err(err) = FileExt::unlock(&self.file) {
+            tracing::warn!("failed to unlock {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Saves `token` to `store`, if any, handing the token back inside the error on failure.
+pub(crate) fn persist(
+    store: Option<&dyn TokenStore>,
+    token: ApiToken,
+) -> Result<ApiToken, QuestradeError> {
+    if let Some(store) = store {
+        if let Err(err) = store.save(&token) {
+            tracing::error!("failed to persist refreshed token: {}", err);
+            return Err(QuestradeError::UnpersistedToken(
+                Box::new(token),
+                err.to_string(),
+            ));
+        }
+    }
+    Ok(token)
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it into place, so readers
+/// never observe a partially written file.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), QuestradeError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| QuestradeError::StoreError(format!("invalid path {}", path.display())))?;
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        Uuid::new_v4()
+    ));
+
+    let result = (|| {
+        let mut file = private_file_options().open(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        #[cfg(unix)]
+        fs::File::open(dir)?.sync_all()?;
+        Ok::<_, std::io::Error>(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result.map_err(|err| {
+        QuestradeError::StoreError(format!("failed to write {}: {}", path.display(), err))
+    })
+}
+
+fn private_file_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+pub(crate) fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, QuestradeError> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(QuestradeError::StoreError(format!(
+            "failed to read {}: {}",
+            path.display(),
+            err
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(b"second".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_creates_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        write_atomic(&path, b"secret").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn store_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+
+        let lock = StoreLock::acquire(&path).unwrap();
+        let other = fs::File::open(lock.path()).unwrap();
+        assert!(!FileExt::try_lock_exclusive(&other).unwrap());
+
+        drop(lock);
+        assert!(FileExt::try_lock_exclusive(&other).unwrap());
+    }
+
+    #[test]
+    fn read_optional_missing_file_works() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(None, read_optional(&dir.path().join("missing")).unwrap());
+    }
+}
@@ -1 +1,512 @@
+use std::collections::HashSet;
 
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::ApiToken, errors::QuestradeError, response::Response, Client, Currency, ListingExchange,
+    OptionDurationType, OptionExerciseType, OptionType, SecurityType,
+};
+
+/// Longest comma separated list of ids or names sent in a single query, after encoding. Longer
+/// lists are split over several requests.
+///
+/// Questrade documents no limit, only that over-long arguments fail with
+/// [`ApiErrorCode::ArgumentTooLong`](crate::errors::ApiErrorCode::ArgumentTooLong). The value is
+/// our own choice: it keeps the whole request line, with the base URL and path added, under the
+/// 2048 characters that is the smallest URL limit commonly enforced by proxies and servers.
+pub(crate) const MAX_QUERY_LIST_LEN: usize = 1500;
+
+/// A match returned by the symbol search.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EquitySymbol {
+    pub symbol: String,
+    pub symbol_id: i64,
+    pub description: String,
+    pub security_type: SecurityType,
+    pub listing_exchange: ListingExchange,
+    pub is_tradable: bool,
+    pub is_quotable: bool,
+    pub currency: Currency,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MinTick {
+    /// The price from which `min_tick` applies.
+    pub pivot: f64,
+    pub min_tick: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnderlyingMultiplierPair {
+    pub multiplier: i64,
+    pub underlying_symbol: String,
+    pub underlying_symbol_id: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OptionDeliverables {
+    pub underlyings: Vec<UnderlyingMultiplierPair>,
+    pub cash_in_lieu: f64,
+}
+
+/// Everything the API knows about a symbol. Fundamentals are missing for symbols they do not
+/// apply to, and the option fields are only set for options.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolDetails {
+    pub symbol: String,
+    pub symbol_id: i64,
+    pub description: String,
+    pub security_type: SecurityType,
+    pub listing_exchange: ListingExchange,
+    pub currency: Currency,
+    pub is_tradable: bool,
+    pub is_quotable: bool,
+    pub has_options: bool,
+    pub prev_day_close_price: Option<f64>,
+    pub high_price52: Option<f64>,
+    pub low_price52: Option<f64>,
+    pub average_vol3_months: Option<i64>,
+    pub average_vol20_days: Option<i64>,
+    pub outstanding_shares: Option<i64>,
+    pub eps: Option<f64>,
+    pub pe: Option<f64>,
+    pub dividend: Option<f64>,
+    #[serde(rename = "yield")]
+    pub dividend_yield: Option<f64>,
+    pub ex_date: Option<DateTime<Utc>>,
+    pub dividend_date: Option<DateTime<Utc>>,
+    pub market_cap: Option<f64>,
+    pub trade_unit: Option<i64>,
+    pub option_type: Option<OptionType>,
+    pub option_duration_type: Option<OptionDurationType>,
+    pub option_root: Option<String>,
+    pub option_contract_deliverables: Option<OptionDeliverables>,
+    pub option_exercise_type: Option<OptionExerciseType>,
+    pub option_expiry_date: Option<DateTime<Utc>>,
+    pub option_strike_price: Option<f64>,
+    #[serde(default)]
+    pub min_ticks: Vec<MinTick>,
+    pub industry_sector: Option<String>,
+    pub industry_group: Option<String>,
+    pub industry_sub_group: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SymbolsData {
+    pub(crate) symbols: Vec<SymbolDetails>,
+}
+
+impl Client {
+    /// Details of `symbol_id`, failing with [`QuestradeError::UnresolvedSymbol`] if Questrade
+    /// returns none.
+    pub async fn symbol(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<SymbolDetails, QuestradeError> {
+        self.symbol_with_meta(token, symbol_id)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn symbol_with_meta(
+        &self,
+        token: &ApiToken,
+        symbol_id: i64,
+    ) -> Result<Response<SymbolDetails>, QuestradeError> {
+        let response: Response<SymbolsData> = self
+            .send_with_meta(self.base_request(
+                Method::GET,
+                token,
+                &format!("v1/symbols/{}", symbol_id),
+            )?)
+            .await?;
+        single_symbol(response, symbol_id)
+    }
+
+    /// Details of each of `symbol_ids`. Long lists are fetched with several requests.
+    pub async fn symbols_by_ids(
+        &self,
+        token: &ApiToken,
+        symbol_ids: Vec<i64>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        let ids: Vec<String> = symbol_ids.iter().map(|id| id.to_string()).collect();
+        self.symbols_by("ids", token, &ids).await
+    }
+
+    /// Details of each of `names`, e.g. `BMO.TO`. Long lists are fetched with several requests.
+    pub async fn symbols_by_names(
+        &self,
+        token: &ApiToken,
+        names: Vec<String>,
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        self.symbols_by("names", token, &names).await
+    }
+
+    async fn symbols_by(
+        &self,
+        key: &str,
+        token: &ApiToken,
+        values: &[String],
+    ) -> Result<Vec<SymbolDetails>, QuestradeError> {
+        let mut symbols = Vec::new();
+        for chunk in query_lists(values, MAX_QUERY_LIST_LEN) {
+            let data: SymbolsData = self
+                .send(
+                    self.base_request(Method::GET, token, "v1/symbols")?
+                        .query(&[(key, chunk)]),
+                )
+                .await?;
+            symbols.extend(data.symbols);
+        }
+        Ok(symbols)
+    }
+
+    /// Symbols starting with `prefix`, beginning at the `offset`th match.
+    pub async fn symbol_search(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        self.symbol_search_with_meta(token, prefix, offset)
+            .await
+            .map(Response::into_data)
+    }
+
+    pub async fn symbol_search_with_meta(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+        offset: u32,
+    ) -> Result<Response<Vec<EquitySymbol>>, QuestradeError> {
+        #[derive(Deserialize)]
+        pub struct Data {
+            pub symbols: Vec<EquitySymbol>,
+        }
+
+        let response: Response<Data> = self
+            .send_with_meta(
+                self.base_request(Method::GET, token, "v1/symbols/search")?
+                    .query(&[
+                        ("prefix", prefix.to_string()),
+                        ("offset", offset.to_string()),
+                    ]),
+            )
+            .await?;
+        Ok(response.map(|data| data.symbols))
+    }
+
+    /// Pages through [`Client::symbol_search`] until no new matches come back.
+    pub async fn symbol_search_all(
+        &self,
+        token: &ApiToken,
+        prefix: &str,
+    ) -> Result<Vec<EquitySymbol>, QuestradeError> {
+        let mut pages = SearchPages::default();
+        while let Some(offset) = pages.next_offset() {
+            let page = self.symbol_search(token, prefix, offset).await?;
+            pages.add(page);
+        }
+        Ok(pages.into_symbols())
+    }
+}
+
+/// Collects search pages, stopping on an empty page or one with nothing new in it. Symbols that
+/// show up on more than one page are kept once, but still count towards the next offset.
+#[derive(Debug, Default)]
+pub(crate) struct SearchPages {
+    symbols: Vec<EquitySymbol>,
+    seen: HashSet<i64>,
+    offset: u32,
+    done: bool,
+}
+
+impl SearchPages {
+    pub(crate) fn next_offset(&self) -> Option<u32> {
+        if self.done {
+            None
+        } else {
+            Some(self.offset)
+        }
+    }
+
+    pub(crate) fn add(&mut self, page: Vec<EquitySymbol>) {
+        let before = self.symbols.len();
+        self.offset += page.len() as u32;
+        self.done = page.is_empty();
+        for symbol in page {
+            if self.seen.insert(symbol.symbol_id) {
+                self.symbols.push(symbol);
+            }
+        }
+        self.done |= self.symbols.len() == before;
+    }
+
+    pub(crate) fn into_symbols(self) -> Vec<EquitySymbol> {
+        self.symbols
+    }
+}
+
+/// The one symbol a lookup by id returns.
+pub(crate) fn single_symbol(
+    mut response: Response<SymbolsData>,
+    symbol_id: i64,
+) -> Result<Response<SymbolDetails>, QuestradeError> {
+    let details = std::mem::take(&mut response.data.symbols)
+        .into_iter()
+        .next()
+        .ok_or_else(|| QuestradeError::UnresolvedSymbol(symbol_id.to_string()))?;
+    Ok(response.map(|_| details))
+}
+
+/// Joins `values` into comma separated lists that stay within `max_len` once URL encoded.
+pub(crate) fn query_lists(values: &[String], max_len: usize) -> Vec<String> {
+    // Commas are sent percent encoded.
+    const SEPARATOR_LEN: usize = 3;
+
+    let mut lists = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for value in values {
+        let len: usize = url::form_urlencoded::byte_serialize(value.as_bytes())
+            .map(str::len)
+            .sum();
+        if !current.is_empty() && current_len + SEPARATOR_LEN + len > max_len {
+            lists.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if !current.is_empty() {
+            current.push(',');
+            current_len += SEPARATOR_LEN;
+        }
+        current.push_str(value);
+        current_len += len;
+    }
+    if !current.is_empty() {
+        lists.push(current);
+    }
+    lists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{test_client, FakeTokens, TestResponse, TestServer};
+
+    const BMO: &str = r#"{
+        "symbol": "BMO",
+        "symbolId": 9292,
+        "description": "BANK OF MONTREAL",
+        "securityType": "Stock",
+        "listingExchange": "NYSE",
+        "isTradable": true,
+        "isQuotable": true,
+        "currency": "USD"
+    }"#;
+
+    const BMO_TO: &str = r#"{
+        "symbol": "BMO.TO",
+        "symbolId": 9291,
+        "description": "BANK OF MONTREAL",
+        "securityType": "Stock",
+        "listingExchange": "TSX",
+        "isTradable": true,
+        "isQuotable": true,
+        "currency": "CAD"
+    }"#;
+
+    #[test]
+    fn equity_symbol_deserialize_works() {
+        let expected = EquitySymbol {
+            symbol: String::from("BMO"),
+            symbol_id: 9292,
+            description: String::from("BANK OF MONTREAL"),
+            security_type: SecurityType::Stock,
+            listing_exchange: ListingExchange::NYSE,
+            is_tradable: true,
+            is_quotable: true,
+            currency: Currency::USD,
+        };
+        assert_eq!(expected, serde_json::from_str(BMO).unwrap());
+
+        let other: EquitySymbol = serde_json::from_str(
+            &BMO.replace("\"NYSE\"", "\"BATS\"")
+                .replace("\"Stock\"", "\"Warrant\""),
+        )
+        .unwrap();
+        assert_eq!(
+            ListingExchange::Other(String::from("BATS")),
+            other.listing_exchange
+        );
+        assert_eq!(
+            SecurityType::Other(String::from("Warrant")),
+            other.security_type
+        );
+    }
+
+    #[tokio::test]
+    async fn symbol_search_all_pages_through_results() {
+        let server = TestServer::start(|request| {
+            let symbols = match request.target.as_str() {
+                "/v1/symbols/search?prefix=BMO&offset=0" => format!("[{}]", BMO),
+                "/v1/symbols/search?prefix=BMO&offset=1" => format!("[{}]", BMO_TO),
+                _ => String::from("[]"),
+            };
+            TestResponse::json(200, &format!(r#"{{"symbols": {}}}"#, symbols))
+        })
+        .await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        let symbols = client.symbol_search_all(&token, "BMO").await.unwrap();
+        let ids: Vec<i64> = symbols.iter().map(|s| s.symbol_id).collect();
+        assert_eq!(vec![9292, 9291], ids);
+        assert_eq!(3, server.requests().len());
+    }
+
+    #[test]
+    fn search_pages_advance_past_duplicates() {
+        let bmo: EquitySymbol = serde_json::from_str(BMO).unwrap();
+        let bmo_to: EquitySymbol = serde_json::from_str(BMO_TO).unwrap();
+        let mut pages = SearchPages::default();
+        pages.add(vec![bmo.clone(), bmo.clone()]);
+        assert_eq!(Some(2), pages.next_offset());
+        pages.add(vec![bmo, bmo_to]);
+        assert_eq!(Some(4), pages.next_offset());
+        pages.add(Vec::new());
+        assert_eq!(None, pages.next_offset());
+        assert_eq!(2, pages.into_symbols().len());
+    }
+
+    #[test]
+    fn search_pages_stop_when_nothing_new_comes_back() {
+        let bmo: EquitySymbol = serde_json::from_str(BMO).unwrap();
+        let mut pages = SearchPages::default();
+        assert_eq!(Some(0), pages.next_offset());
+        pages.add(vec![bmo.clone()]);
+        assert_eq!(Some(1), pages.next_offset());
+        pages.add(vec![bmo]);
+        assert_eq!(None, pages.next_offset());
+        assert_eq!(1, pages.into_symbols().len());
+    }
+
+    const AAPL_DETAILS: &str = r#"
+    {
+        "symbols": [
+            {
+                "symbol": "AAPL",
+                "symbolId": 8049,
+                "prevDayClosePrice": 102.5,
+                "highPrice52": 102.9,
+                "lowPrice52": 63.89,
+                "averageVol3Months": 43769680,
+                "averageVol20Days": 12860370,
+                "outstandingShares": 5934257000,
+                "eps": 6.2,
+                "pe": 16.53,
+                "dividend": 0.47,
+                "yield": 1.8,
+                "exDate": "2014-08-07T00:00:00.000000-04:00",
+                "marketCap": 608301709000,
+                "tradeUnit": 1,
+                "optionType": null,
+                "optionDurationType": null,
+                "optionRoot": "",
+                "optionContractDeliverables": {
+                    "underlyings": [],
+                    "cashInLieu": 0
+                },
+                "optionExerciseType": null,
+                "listingExchange": "NASDAQ",
+                "description": "APPLE INC",
+                "securityType": "Stock",
+                "optionExpiryDate": null,
+                "dividendDate": "2014-08-14T00:00:00.000000-04:00",
+                "optionStrikePrice": null,
+                "isTradable": true,
+                "isQuotable": true,
+                "hasOptions": true,
+                "currency": "USD",
+                "minTicks": [
+                    { "pivot": 0, "minTick": 0.0001 },
+                    { "pivot": 1, "minTick": 0.01 }
+                ],
+                "industrySector": "Technology",
+                "industryGroup": "ConsumerElectronics",
+                "industrySubGroup": "ConsumerElectronics"
+            }
+        ]
+    }
+    "#;
+
+    #[tokio::test]
+    async fn symbol_returns_the_single_match() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/v1/symbols/8049" => TestResponse::json(200, AAPL_DETAILS),
+            _ => TestResponse::json(200, r#"{"symbols": []}"#),
+        })
+        .await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+
+        assert_eq!(8049, client.symbol(&token, 8049).await.unwrap().symbol_id);
+        let err = client.symbol(&token, 1).await.unwrap_err();
+        assert!(matches!(err, QuestradeError::UnresolvedSymbol(id) if id == "1"));
+    }
+
+    #[test]
+    fn symbol_details_deserialize_works() {
+        let d: SymbolsData =
+            serde_json::from_str(AAPL_DETAILS).expect("failed to deserialize JSON");
+        let aapl = d.symbols.first().unwrap();
+        assert_eq!(8049, aapl.symbol_id);
+        assert_eq!(Some(1.8), aapl.dividend_yield);
+        assert_eq!(Some(608301709000.0), aapl.market_cap);
+        assert_eq!(
+            Some(
+                DateTime::parse_from_rfc3339("2014-08-07T00:00:00.000000-04:00")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            ),
+            aapl.ex_date
+        );
+        assert_eq!(None, aapl.option_type);
+        assert_eq!(2, aapl.min_ticks.len());
+        assert_eq!(Some("Technology"), aapl.industry_sector.as_deref());
+    }
+
+    #[test]
+    fn query_lists_stay_within_limit() {
+        let names: Vec<String> = (0..10).map(|i| format!("SYM{}.TO", i)).collect();
+        assert_eq!(vec![names.join(",")], query_lists(&names, 1500));
+
+        let lists = query_lists(&names, 20);
+        assert_eq!(vec!["SYM0.TO,SYM1.TO", "SYM2.TO,SYM3.TO"], lists[..2]);
+        assert_eq!(5, lists.len());
+        assert!(query_lists(&[], 20).is_empty());
+    }
+
+    #[tokio::test]
+    async fn symbols_by_names_chunks_long_lists() {
+        let server = TestServer::start(|_| TestResponse::json(200, r#"{"symbols": []}"#)).await;
+        let client = test_client();
+        let token = FakeTokens::new(&server.url).token("a1");
+        let names: Vec<String> = (0..400).map(|i| format!("SYMBOL{}.TO", i)).collect();
+
+        client.symbols_by_names(&token, names).await.unwrap();
+        let requests = server.requests();
+        assert!(requests.len() > 1);
+        assert!(requests
+            .iter()
+            .all(|r| r.target.starts_with("/v1/symbols?names=SYMBOL")
+                && r.target.len() < MAX_QUERY_LIST_LEN + 30));
+    }
+}
@@ -0,0 +1,238 @@
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(test)]
+use crate::{auth::ApiToken, client::Client, errors::QuestradeError, token::TokenSource};
+
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn json(status: u16, body: &str) -> Self {
+        TestResponse {
+            status,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// A minimal HTTP/1.1 server answering every request with the response produced by `handler`.
+pub(crate) struct TestServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl TestServer {
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&RecordedRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                // Accepting fails transiently, e.g. when out of file descriptors.
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        tracing::warn!("test server failed to accept a connection: {}", err);
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                        continue;
+                    }
+                };
+                let recorded = recorded.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move { handle(stream, &*handler, &recorded).await });
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle<F>(
+    mut stream: TcpStream,
+    handler: &F,
+    recorded: &Mutex<Vec<RecordedRequest>>,
+) -> Option<()>
+where
+    F: Fn(&RecordedRequest) -> TestResponse,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let request = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let raw = String::from_utf8_lossy(&buf).into_owned();
+        let (head, body) = match raw.split_once("\r\n\r\n") {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let target = request_line.next()?.to_string();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+        let length = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        if body.len() >= length {
+            break RecordedRequest {
+                method,
+                target,
+                headers,
+                body: body.to_string(),
+            };
+        }
+    };
+
+    let response = handler(&request);
+    recorded.lock().unwrap().push(request);
+    let mut raw = format!("HTTP/1.1 {} Test\r\n", response.status);
+    for (name, value) in &response.headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.body.len(),
+        response.body
+    ));
+    stream.write_all(raw.as_bytes()).await.ok()
+}
+
+/// A client builder for tests against a [`TestServer`], which speaks plain http.
+#[cfg(test)]
+pub(crate) fn test_client_builder() -> crate::client::ClientBuilder {
+    Client::builder()
+        .http_client(reqwest::Client::new())
+        .consumer_key(String::from("consumer"))
+        .allow_insecure(true)
+}
+
+#[cfg(test)]
+pub(crate) fn test_client() -> Client {
+    test_client_builder().build().unwrap()
+}
+
+#[cfg(all(test, feature = "blocking"))]
+pub(crate) fn test_blocking_client_builder() -> crate::blocking::ClientBuilder {
+    crate::blocking::Client::builder()
+        .http_client(reqwest::blocking::Client::new())
+        .consumer_key(String::from("consumer"))
+        .allow_insecure(true)
+}
+
+/// A token for `api_server`, valid for 30 minutes.
+#[cfg(test)]
+pub(crate) fn test_token(access_token: &str, api_server: &str) -> ApiToken {
+    ApiToken {
+        access_token: access_token.into(),
+        token_type: "Bearer".into(),
+        refresh_token: "refresh".into(),
+        api_server: api_server.into(),
+        expires_in: 1800,
+        issued_at: chrono::Utc::now(),
+    }
+}
+
+/// Hands out access token `a1` until refreshed, then `a2` served from `refreshed_api_server`.
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct FakeTokens {
+    pub api_server: String,
+    pub refreshed_api_server: String,
+    pub refreshes: AtomicUsize,
+}
+
+#[cfg(test)]
+impl FakeTokens {
+    pub fn new(api_server: &str) -> Self {
+        FakeTokens {
+            api_server: api_server.into(),
+            refreshed_api_server: api_server.into(),
+            refreshes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn token(&self, access_token: &str) -> ApiToken {
+        let api_server = if access_token == "a1" {
+            &self.api_server
+        } else {
+            &self.refreshed_api_server
+        };
+        test_token(access_token, api_server)
+    }
+
+    pub fn refreshes(&self) -> usize {
+        self.refreshes.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl TokenSource for FakeTokens {
+    async fn token(&self, _client: &Client) -> Result<ApiToken, QuestradeError> {
+        Ok(self.token(if self.refreshes() == 0 { "a1" } else { "a2" }))
+    }
+
+    async fn refresh(
+        &self,
+        _client: &Client,
+        stale_access_token: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        assert_eq!("a1", stale_access_token);
+        self.refreshes.fetch_add(1, Ordering::SeqCst);
+        Ok(self.token("a2"))
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt::Debug, future::Future, sync::RwLock, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::{auth::ApiToken, client::Client, errors::QuestradeError};
+
+pub(crate) const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Something that can hand out valid access tokens, either by refreshing them itself like
+/// [`TokenManager`] or by asking another process like
+/// [`BrokerTokenSource`](crate::broker::BrokerTokenSource).
+#[async_trait]
+pub trait TokenSource: Debug + Send + Sync {
+    /// Returns a token that is not about to expire.
+    async fn token(&self, client: &Client) -> Result<ApiToken, QuestradeError>;
+
+    /// Called after `stale_access_token` was rejected by the API, returns a replacement.
+    async fn refresh(
+        &self,
+        client: &Client,
+        stale_access_token: &str,
+    ) -> Result<ApiToken, QuestradeError>;
+}
+
+/// Owns an [`ApiToken`] and keeps it fresh. Questrade refresh tokens are single use, so refreshes
+/// are single-flight: when many tasks find the token expiring at once only one of them refreshes
+/// and the rest wait for, then reuse, its result.
+///
+/// A `TokenManager` is `Send + Sync` and is meant to be shared behind an `Arc`.
+#[derive(Debug)]
+pub struct TokenManager {
+    token: RwLock<ApiToken>,
+    refresh_lock: Mutex<()>,
+    refresh_margin: Duration,
+}
+
+impl TokenManager {
+    pub fn new(token: ApiToken) -> Self {
+        TokenManager {
+            token: RwLock::new(token),
+            refresh_lock: Mutex::new(()),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+        }
+    }
+
+    /// How long before expiry the token is proactively refreshed, defaults to 60 seconds.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// The current token, without checking whether it has expired.
+    pub fn current(&self) -> ApiToken {
+        self.token.read().unwrap().clone()
+    }
+
+    /// Returns a token that is valid for at least the refresh margin, refreshing it if needed.
+    pub async fn token(&self, client: &Client) -> Result<ApiToken, QuestradeError> {
+        self.token_with(|refresh_token| async move {
+            client
+                .refresh_token_within(&refresh_token, self.refresh_margin)
+                .await
+        })
+        .await
+    }
+
+    /// Forces a refresh after `stale_access_token` was rejected by the API. If another task has
+    /// already replaced that token the current one is returned instead of refreshing again.
+    pub async fn refresh(
+        &self,
+        client: &Client,
+        stale_access_token: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        self.refresh_with(stale_access_token, |refresh_token| async move {
+            client
+                .refresh_token_within(&refresh_token, self.refresh_margin)
+                .await
+        })
+        .await
+    }
+
+    pub(crate) async fn token_with<F, Fut>(&self, refresh: F) -> Result<ApiToken, QuestradeError>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<ApiToken, QuestradeError>>,
+    {
+        let token = self.current();
+        if !token.expires_within(self.refresh_margin) {
+            return Ok(token);
+        }
+
+        let _guard = self.refresh_lock.lock().await;
+        let token = self.current();
+        if !token.expires_within(self.refresh_margin) {
+            return Ok(token);
+        }
+        self.replace(refresh, &token.refresh_token).await
+    }
+
+    pub(crate) async fn refresh_with<F, Fut>(
+        &self,
+        stale_access_token: &str,
+        refresh: F,
+    ) -> Result<ApiToken, QuestradeError>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<ApiToken, QuestradeError>>,
+    {
+        let _guard = self.refresh_lock.lock().await;
+        let token = self.current();
+        if token.access_token != stale_access_token {
+            return Ok(token);
+        }
+        self.replace(refresh, &token.refresh_token).await
+    }
+
+    async fn replace<F, Fut>(
+        &self,
+        refresh: F,
+        refresh_token: &str,
+    ) -> Result<ApiToken, QuestradeError>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<ApiToken, QuestradeError>>,
+    {
+        tracing::debug!("refreshing access token");
+        let token = refresh(String::from(refresh_token)).await?;
+        *self.token.write().unwrap() = token.clone();
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl TokenSource for TokenManager {
+    async fn token(&self, client: &Client) -> Result<ApiToken, QuestradeError> {
+        TokenManager::token(self, client).await
+    }
+
+    async fn refresh(
+        &self,
+        client: &Client,
+        stale_access_token: &str,
+    ) -> Result<ApiToken, QuestradeError> {
+        TokenManager::refresh(self, client, stale_access_token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::test_server::test_token;
+
+    fn token(access_token: &str, refresh_token: &str, expires_in: usize) -> ApiToken {
+        ApiToken {
+            refresh_token: refresh_token.into(),
+            expires_in,
+            ..test_token(access_token, "https://api01.iq.questrade.com/")
+        }
+    }
+
+    #[tokio::test]
+    async fn token_returns_fresh_token_without_refreshing() {
+        let manager = TokenManager::new(token("a1", "r1", 1800));
+        let t = manager
+            .token_with(|_| async { panic!("should not refresh") })
+            .await
+            .unwrap();
+        assert_eq!("a1", t.access_token);
+    }
+
+    #[tokio::test]
+    async fn token_refreshes_expiring_token_once() {
+        let manager = Arc::new(TokenManager::new(token("a1", "r1", 30)));
+        let refreshes = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                let manager = manager.clone();
+                let refreshes = refreshes.clone();
+                tokio::spawn(async move {
+                    manager
+                        .token_with(|refresh_token| async move {
+                            assert_eq!("r1", refresh_token);
+                            refreshes.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            Ok(token("a2", "r2", 1800))
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!("a2", task.await.unwrap().unwrap().access_token);
+        }
+        assert_eq!(1, refreshes.load(Ordering::SeqCst));
+        assert_eq!("r2", manager.current().refresh_token);
+    }
+
+    #[tokio::test]
+    async fn refresh_skips_already_rotated_token() {
+        let manager = TokenManager::new(token("a2", "r2", 1800));
+        let t = manager
+            .refresh_with("a1", |_| async { panic!("should not refresh") })
+            .await
+            .unwrap();
+        assert_eq!("a2", t.access_token);
+
+        let t = manager
+            .refresh_with("a2", |_| async { Ok(token("a3", "r3", 1800)) })
+            .await
+            .unwrap();
+        assert_eq!("a3", t.access_token);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_keeps_current_token() {
+        let manager = TokenManager::new(token("a1", "r1", 0));
+        let err = manager
+            .token_with(|_| async {
+                Err(QuestradeError::TransportError {
+                    broker: String::from("broker.sock"),
+                    source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+                })
+            })
+            .await;
+        assert!(err.is_err());
+        assert_eq!("r1", manager.current().refresh_token);
+    }
+}
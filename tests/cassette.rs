@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use questrade::{
+    auth::ApiToken, cassette::Cassette, client::Client, rate_limit::RateLimitCategory, Interval,
+    StateFilter,
+};
+
+fn client(cassette: Arc<Cassette>) -> Client {
+    Client::builder()
+        .http_client(reqwest::Client::new())
+        .consumer_key(String::from("consumer"))
+        .allow_insecure(true)
+        .middleware(cassette)
+        .build()
+        .unwrap()
+}
+
+fn token() -> ApiToken {
+    ApiToken {
+        access_token: String::from("access"),
+        token_type: String::from("Bearer"),
+        refresh_token: String::from("refresh"),
+        // Nothing listens here, every request has to be served by the cassette.
+        api_server: String::from("http://127.0.0.1:1/"),
+        expires_in: 1800,
+        issued_at: Utc::now(),
+    }
+}
+
+fn date(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+#[tokio::test]
+async fn endpoints_build_expected_requests() {
+    let cassette = Arc::new(Cassette::replay("tests/cassettes/endpoints.jsonl").unwrap());
+    let client = client(cassette.clone());
+    let token = token();
+    let start = date("2014-10-01T00:00:00Z");
+    let end = date("2014-10-24T00:00:00Z");
+
+    client.time(&token).await.unwrap();
+
+    let accounts = client.accounts(&token).await.unwrap();
+    assert_eq!("26598145", accounts[0].number);
+    assert_eq!(
+        29998,
+        client
+            .rate_limit(RateLimitCategory::Account)
+            .unwrap()
+            .remaining
+    );
+
+    client
+        .account_activities(&token, "26598145", &start, &end)
+        .await
+        .unwrap();
+    client
+        .account_executions(&token, "26598145", Some(&start), None)
+        .await
+        .unwrap();
+    client
+        .account_orders(
+            &token,
+            "26598145",
+            Some(&start),
+            Some(&end),
+            Some(StateFilter::Closed),
+        )
+        .await
+        .unwrap();
+    client
+        .market_quotes_symbols(&token, vec![8049, 9291])
+        .await
+        .unwrap();
+    assert_eq!(
+        14999,
+        client
+            .rate_limit(RateLimitCategory::MarketData)
+            .unwrap()
+            .remaining
+    );
+    client
+        .market_candles(&token, 8049, &start, &end, Interval::OneDay)
+        .await
+        .unwrap();
+
+    assert!(cassette.remaining().is_empty());
+}